@@ -0,0 +1,105 @@
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use hasty::{Config, Engine, EngineOptions, Script};
+use serde_json::json;
+use std::{collections::HashMap, fs, path::Path};
+use tempfile::TempDir;
+
+static SIZES: [usize; 3] = [1_000, 5_000, 10_000];
+
+// Writes a monorepo of `size` packages, each depending on up to three earlier packages and a
+// couple of external ones
+fn synthetic_monorepo(size: usize) -> TempDir {
+    let root = tempfile::tempdir().unwrap();
+
+    write_json(
+        root.path(),
+        &json!({
+            "name": "root",
+            "private": true,
+            "workspaces": ["packages/*"],
+        }),
+    );
+
+    for i in 0..size {
+        let mut dependencies = HashMap::from([
+            (String::from("react"), String::from("^18.0.0")),
+            (String::from("lodash"), String::from("^4.17.0")),
+        ]);
+        for dep in [i.wrapping_sub(1), i / 2, i / 3] {
+            if dep < i {
+                dependencies.insert(format!("pkg-{}", dep), String::from("workspace:*"));
+            }
+        }
+
+        let dir = root.path().join("packages").join(format!("pkg-{}", i));
+        fs::create_dir_all(&dir).unwrap();
+        write_json(
+            &dir,
+            &json!({
+                "name": format!("pkg-{}", i),
+                "version": "1.0.0",
+                "scripts": { "build": "tsc", "format": "prettier --write ." },
+                "dependencies": dependencies,
+            }),
+        );
+    }
+
+    root
+}
+
+fn write_json(dir: &Path, value: &serde_json::Value) {
+    fs::write(dir.join("package.json"), value.to_string()).unwrap();
+}
+
+fn config() -> Config {
+    serde_json::from_value(json!({
+        "pipeline": {
+            "build": { "command": "build", "dependencies": ["format", "^build"] },
+            "format": { "command": "format" },
+        }
+    }))
+    .unwrap()
+}
+
+fn new_engine(dir: &Path) -> Engine {
+    let config = config();
+    let tasks = vec![String::from("build")];
+
+    let mut engine =
+        Engine::new(config.clone(), dir, tasks.clone(), EngineOptions::default()).unwrap();
+
+    // the "__ROOT__" templates that the CLI adds for the called tasks
+    for command_config in config.pipeline.values() {
+        engine.add_script(&Script::new(command_config.clone(), dir, "__ROOT__"));
+    }
+
+    engine
+}
+
+fn build_graphs(c: &mut Criterion) {
+    let mut group = c.benchmark_group("build_graphs");
+    group.sample_size(10);
+
+    for size in SIZES {
+        let repo = synthetic_monorepo(size);
+
+        group.bench_with_input(BenchmarkId::from_parameter(size), &size, |b, _| {
+            b.iter_batched(
+                || new_engine(repo.path()),
+                |mut engine| {
+                    engine.build_package_graph().unwrap();
+                    engine.resolve_workspace_scripts().unwrap();
+                    engine.add_topo_task_deps();
+                    engine.add_deps_to_graph().unwrap();
+                    engine
+                },
+                BatchSize::PerIteration,
+            )
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, build_graphs);
+criterion_main!(benches);
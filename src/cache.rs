@@ -0,0 +1,413 @@
+use glob::glob;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    package_json::PackageJSON, package_manager::PackageManager, shell, ExecutionMode, Script,
+};
+
+static CACHE_DIR: &str = "node_modules/.cache/hasty";
+static OUTPUTS_DIR: &str = "outputs";
+static LOG_FILE: &str = "output.log";
+static COMPLETE_MARKER: &str = ".complete";
+
+// Directories that are never considered task inputs when a task doesn't declare `files`
+static IGNORED_DIRS: [&str; 2] = ["node_modules", ".git"];
+
+/// A local, content-addressed store of task outputs, keyed by the task's hash.
+#[derive(Debug, Clone)]
+pub struct Cache {
+    dir: PathBuf,
+}
+
+impl Cache {
+    pub fn new(root_dir: &Path) -> Self {
+        Cache {
+            dir: root_dir.join(CACHE_DIR),
+        }
+    }
+
+    /// Computes a hash from the script's input files, its resolved script body and hooks, how it
+    /// is run, its package's external dependencies and the hashes of its upstream tasks. Changes
+    /// to workspace dependencies are picked up through the upstream hashes instead.
+    pub fn hash_script(
+        &self,
+        script: &Script,
+        mode: ExecutionMode,
+        package_manager: PackageManager,
+        package: Option<&PackageJSON>,
+        external_dependencies: Option<&BTreeMap<String, String>>,
+        upstream_hashes: &[String],
+    ) -> String {
+        let mut hasher = Sha256::new();
+
+        hasher.update(script.id());
+        hasher.update([0]);
+
+        // the package manager and shell run hooks and set up the environment differently
+        let mode = match mode {
+            ExecutionMode::PackageManager => "package-manager",
+            ExecutionMode::Shell => "shell",
+        };
+        hasher.update(mode);
+        hasher.update([0]);
+        hasher.update(package_manager.binary());
+        hasher.update([0]);
+
+        let config = script.config();
+        for pattern in config.output.iter().flatten() {
+            hasher.update(pattern);
+            hasher.update([0]);
+        }
+
+        if let Some(pkg) = package {
+            // include the pre/post hooks, since they run as part of the script too
+            for (stage, body) in shell::lifecycle_stages(pkg, &script.command) {
+                hasher.update(stage);
+                hasher.update([0]);
+                hasher.update(body);
+                hasher.update([0]);
+            }
+        }
+
+        for (name, version) in external_dependencies.into_iter().flatten() {
+            hasher.update(format!("{}@{}", name, version));
+            hasher.update([0]);
+        }
+
+        for file in input_files(script) {
+            let relative = file.strip_prefix(&script.dir).unwrap_or(&file);
+
+            if let Ok(contents) = fs::read(&file) {
+                hasher.update(relative.to_string_lossy().as_bytes());
+                hasher.update([0]);
+                hasher.update(contents);
+            }
+        }
+
+        let mut upstream_hashes = upstream_hashes.to_vec();
+        upstream_hashes.sort();
+        for hash in upstream_hashes {
+            hasher.update(hash);
+        }
+
+        format!("{:x}", hasher.finalize())
+    }
+
+    /// Restores the cached outputs for the given hash into the script's directory and returns
+    /// the captured log lines of the run that produced them. Returns `None` when there is no
+    /// complete cache entry for the hash.
+    pub fn restore(&self, hash: &str, script: &Script) -> io::Result<Option<Vec<String>>> {
+        let entry_dir = self.dir.join(hash);
+
+        if !entry_dir.join(COMPLETE_MARKER).exists() {
+            return Ok(None);
+        }
+
+        let outputs_dir = entry_dir.join(OUTPUTS_DIR);
+        if outputs_dir.exists() {
+            copy_dir(&outputs_dir, &script.dir)?;
+        }
+
+        let logs = match fs::read_to_string(entry_dir.join(LOG_FILE)) {
+            Ok(raw) => raw.lines().map(String::from).collect(),
+            Err(_) => vec![],
+        };
+
+        Ok(Some(logs))
+    }
+
+    /// Stores the script's declared outputs and the log lines it produced in the cache under the
+    /// given hash.
+    pub fn save(&self, hash: &str, script: &Script, logs: &[String]) -> io::Result<()> {
+        let entry_dir = self.dir.join(hash);
+        let outputs_dir = entry_dir.join(OUTPUTS_DIR);
+
+        if entry_dir.exists() {
+            fs::remove_dir_all(&entry_dir)?;
+        }
+        fs::create_dir_all(&outputs_dir)?;
+
+        for path in output_paths(script) {
+            let relative = match path.strip_prefix(&script.dir) {
+                Ok(relative) => relative,
+                Err(_) => continue,
+            };
+
+            let dest = outputs_dir.join(relative);
+            if path.is_dir() {
+                copy_dir(&path, &dest)?;
+            } else {
+                if let Some(parent) = dest.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                fs::copy(&path, &dest)?;
+            }
+        }
+
+        let mut raw_logs = logs.join("\n");
+        if !raw_logs.is_empty() {
+            raw_logs.push('\n');
+        }
+        fs::write(entry_dir.join(LOG_FILE), raw_logs)?;
+
+        fs::write(entry_dir.join(COMPLETE_MARKER), "")?;
+
+        Ok(())
+    }
+}
+
+// Resolves the files that make up a script's inputs, sorted so the hash is stable. Falls back to
+// every file in the package when the task doesn't declare `files`. A glob matching a directory,
+// such as `src` or `src/**`, includes every file inside of it.
+fn input_files(script: &Script) -> Vec<PathBuf> {
+    let mut files = match &script.config().files {
+        Some(patterns) => {
+            let mut files = vec![];
+            for path in expand_globs(&script.dir, patterns) {
+                if path.is_dir() {
+                    walk_package_files(&path, &mut files);
+                } else if path.is_file() {
+                    files.push(path);
+                }
+            }
+            files
+        }
+        None => {
+            let outputs = output_paths(script);
+            let mut files = vec![];
+            walk_package_files(&script.dir, &mut files);
+            files.retain(|f| !outputs.iter().any(|o| f.starts_with(o)));
+            files
+        }
+    };
+
+    files.sort();
+    files.dedup();
+    files
+}
+
+fn output_paths(script: &Script) -> Vec<PathBuf> {
+    match &script.config().output {
+        Some(patterns) => expand_globs(&script.dir, patterns),
+        None => vec![],
+    }
+}
+
+// Lists the paths under `dir` matched by `patterns`. A trailing `/**` only matches the
+// directories below, so it is treated as the directory itself, whose files callers include.
+fn expand_globs(dir: &Path, patterns: &[String]) -> Vec<PathBuf> {
+    let mut result = vec![];
+
+    for pattern in patterns {
+        let glob_with_root = dir.join(pattern.strip_suffix("/**").unwrap_or(pattern));
+        let entries = match glob(&glob_with_root.to_string_lossy()) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+
+        result.extend(entries.flatten());
+    }
+
+    result
+}
+
+// Collects every file below `dir`, skipping ignored directories and nested packages
+fn walk_package_files(dir: &Path, files: &mut Vec<PathBuf>) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+
+        if path.is_dir() {
+            let name = entry.file_name();
+            if IGNORED_DIRS.iter().any(|d| name == *d) {
+                continue;
+            }
+
+            if path.join("package.json").exists() {
+                continue;
+            }
+
+            walk_package_files(&path, files);
+        } else {
+            files.push(path);
+        }
+    }
+}
+
+fn copy_dir(from: &Path, to: &Path) -> io::Result<()> {
+    fs::create_dir_all(to)?;
+
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let dest = to.join(entry.file_name());
+
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &dest)?;
+        } else {
+            fs::copy(entry.path(), &dest)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CommandConfig;
+    use tempfile::TempDir;
+
+    // A package at `packages/a` inside a workspace root, with a build that writes to `dist`
+    fn create_package(config: &str) -> (TempDir, Script) {
+        let root = tempfile::tempdir().unwrap();
+        let dir = root.path().join("packages/a");
+
+        write(&dir, "package.json", r#"{ "name": "a" }"#);
+        write(&dir, "src/index.js", "module.exports = 1");
+
+        let config: CommandConfig = serde_json::from_str(config).unwrap();
+        let script = Script::new(config, &dir, "a");
+
+        (root, script)
+    }
+
+    fn write(dir: &Path, path: &str, contents: &str) {
+        let path = dir.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    fn hash(cache: &Cache, script: &Script, upstream_hashes: &[&str]) -> String {
+        let upstream_hashes = upstream_hashes
+            .iter()
+            .map(|h| h.to_string())
+            .collect::<Vec<String>>();
+
+        cache.hash_script(
+            script,
+            ExecutionMode::PackageManager,
+            PackageManager::Npm,
+            None,
+            None,
+            &upstream_hashes,
+        )
+    }
+
+    #[test]
+    fn restoring_an_unknown_hash_is_a_miss() {
+        let (root, script) = create_package(r#"{ "command": "build", "output": ["dist"] }"#);
+        let cache = Cache::new(root.path());
+
+        assert_eq!(cache.restore("unknown", &script).unwrap(), None);
+    }
+
+    #[test]
+    fn saved_outputs_and_logs_are_restored() {
+        let (root, script) = create_package(r#"{ "command": "build", "output": ["dist/**"] }"#);
+        let cache = Cache::new(root.path());
+        write(&script.dir, "dist/index.js", "built");
+        write(&script.dir, "dist/nested/chunk.js", "chunk");
+
+        let hash = hash(&cache, &script, &[]);
+        let logs = vec![String::from("building"), String::from("done")];
+        cache.save(&hash, &script, &logs).unwrap();
+
+        fs::remove_dir_all(script.dir.join("dist")).unwrap();
+
+        assert_eq!(cache.restore(&hash, &script).unwrap(), Some(logs));
+        assert_eq!(
+            fs::read_to_string(script.dir.join("dist/index.js")).unwrap(),
+            "built"
+        );
+        assert_eq!(
+            fs::read_to_string(script.dir.join("dist/nested/chunk.js")).unwrap(),
+            "chunk"
+        );
+    }
+
+    #[test]
+    fn entries_without_the_complete_marker_are_a_miss() {
+        let (root, script) = create_package(r#"{ "command": "build", "output": ["dist"] }"#);
+        let cache = Cache::new(root.path());
+
+        let hash = hash(&cache, &script, &[]);
+        cache.save(&hash, &script, &[]).unwrap();
+        fs::remove_file(cache.dir.join(&hash).join(COMPLETE_MARKER)).unwrap();
+
+        assert_eq!(cache.restore(&hash, &script).unwrap(), None);
+    }
+
+    #[test]
+    fn hash_changes_with_inputs_but_not_outputs() {
+        let (root, script) = create_package(r#"{ "command": "build", "output": ["dist"] }"#);
+        let cache = Cache::new(root.path());
+        let initial = hash(&cache, &script, &[]);
+
+        write(&script.dir, "dist/index.js", "built");
+        write(&script.dir, "node_modules/dep/index.js", "installed");
+        assert_eq!(hash(&cache, &script, &[]), initial);
+
+        write(&script.dir, "src/index.js", "module.exports = 2");
+        assert_ne!(hash(&cache, &script, &[]), initial);
+    }
+
+    #[test]
+    fn hash_changes_with_how_the_script_is_run() {
+        let (root, script) = create_package(r#"{ "command": "build" }"#);
+        let cache = Cache::new(root.path());
+        let hash_with = |mode, package_manager| {
+            cache.hash_script(&script, mode, package_manager, None, None, &[])
+        };
+
+        let initial = hash(&cache, &script, &[]);
+        assert_eq!(
+            hash_with(ExecutionMode::PackageManager, PackageManager::Npm),
+            initial
+        );
+        assert_ne!(
+            hash_with(ExecutionMode::Shell, PackageManager::Npm),
+            initial
+        );
+        assert_ne!(
+            hash_with(ExecutionMode::PackageManager, PackageManager::Pnpm),
+            initial
+        );
+    }
+
+    #[test]
+    fn hash_only_reads_the_declared_files() {
+        let (root, script) = create_package(r#"{ "command": "build", "files": ["src/**"] }"#);
+        let cache = Cache::new(root.path());
+        let initial = hash(&cache, &script, &[]);
+
+        write(&script.dir, "README.md", "# a");
+        assert_eq!(hash(&cache, &script, &[]), initial);
+
+        write(&script.dir, "src/other.js", "module.exports = 3");
+        assert_ne!(hash(&cache, &script, &[]), initial);
+    }
+
+    #[test]
+    fn hash_includes_upstream_hashes_in_any_order() {
+        let (root, script) = create_package(r#"{ "command": "build" }"#);
+        let cache = Cache::new(root.path());
+
+        assert_eq!(
+            hash(&cache, &script, &["one", "two"]),
+            hash(&cache, &script, &["two", "one"])
+        );
+        assert_ne!(
+            hash(&cache, &script, &["one", "two"]),
+            hash(&cache, &script, &["one", "three"])
+        );
+    }
+}
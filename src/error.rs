@@ -0,0 +1,164 @@
+use std::{error, fmt, io, path::PathBuf, time::Duration};
+
+use crate::shutdown::Signal;
+
+pub type Result<T> = std::result::Result<T, HastyError>;
+
+#[derive(Debug)]
+pub enum HastyError {
+    /// A file couldn't be read
+    Io { path: PathBuf, source: io::Error },
+    /// A file couldn't be parsed as JSON
+    Json {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+    /// A file couldn't be parsed as YAML
+    Yaml {
+        path: PathBuf,
+        source: serde_yaml::Error,
+    },
+    /// A hasty.json was valid JSON but not a valid config
+    InvalidConfig { path: PathBuf, message: String },
+    /// A workspace glob couldn't be parsed
+    Glob {
+        pattern: String,
+        source: glob::PatternError,
+    },
+    /// A task was requested, or depended on, that the pipeline doesn't define
+    UnknownTask {
+        task: String,
+        dependent: Option<String>,
+    },
+    /// A task depends on a `package#task` that the package doesn't have a script for
+    MissingScript { task: String, dependent: String },
+    /// A task depends on a persistent task without a readiness probe, which never finishes
+    PersistentDependency { task: String, dependent: String },
+    /// The task graph contains a cycle, listed as the chain of task ids that form it
+    TaskCycle(Vec<String>),
+    /// The package graph contains a cycle, listed as the chain of package names that form it
+    PackageCycle(Vec<String>),
+    /// A task's process couldn't be spawned or waited on
+    Process { task: String, source: io::Error },
+    /// A task was stopped because hasty received a signal
+    Interrupted { task: String, signal: Signal },
+    /// A task ran for longer than its timeout and was stopped
+    TimedOut { task: String, timeout: Duration },
+    /// The `git` binary failed while determining changed files
+    Git(io::Error),
+    /// The file system couldn't be watched for changes
+    Watch(notify::Error),
+    /// A plan or graph couldn't be written to stdout
+    Output(io::Error),
+    /// Handlers for SIGINT and SIGTERM couldn't be installed
+    Signal(io::Error),
+}
+
+impl fmt::Display for HastyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HastyError::Io { path, source } => {
+                write!(f, "unable to read {}: {}", path.display(), source)
+            }
+            HastyError::Json { path, source } => {
+                // serde_json appends the position to its messages, which we already include
+                let message = source.to_string();
+                let position = format!(" at line {} column {}", source.line(), source.column());
+                let message = message.strip_suffix(&position).unwrap_or(&message);
+
+                write!(
+                    f,
+                    "invalid JSON in {}:{}:{}: {}",
+                    path.display(),
+                    source.line(),
+                    source.column(),
+                    message
+                )
+            }
+            HastyError::Yaml { path, source } => {
+                write!(f, "invalid YAML in {}: {}", path.display(), source)
+            }
+            HastyError::InvalidConfig { path, message } => {
+                write!(f, "invalid config {}: {}", path.display(), message)
+            }
+            HastyError::Glob { pattern, source } => {
+                write!(f, "invalid workspace glob {}: {}", pattern, source)
+            }
+            HastyError::UnknownTask {
+                task,
+                dependent: Some(dependent),
+            } => write!(
+                f,
+                "{} depends on {}, which is not defined in the pipeline",
+                dependent, task
+            ),
+            HastyError::UnknownTask {
+                task,
+                dependent: None,
+            } => write!(f, "the pipeline does not contain a task named {}", task),
+            HastyError::MissingScript { task, dependent } => write!(
+                f,
+                "{} depends on {}, which is not a script in the workspace",
+                dependent, task
+            ),
+            HastyError::PersistentDependency { task, dependent } => write!(
+                f,
+                "{} depends on {}, which is persistent and never finishes, give it a readiness probe",
+                dependent, task
+            ),
+            HastyError::TaskCycle(cycle) => {
+                write!(
+                    f,
+                    "cycle detected in the task graph: {}",
+                    cycle.join(" -> ")
+                )
+            }
+            HastyError::PackageCycle(cycle) => {
+                write!(
+                    f,
+                    "cycle detected in the package graph: {}",
+                    cycle.join(" -> ")
+                )
+            }
+            HastyError::Process { task, source } => {
+                write!(f, "unable to run {}: {}", task, source)
+            }
+            HastyError::Interrupted { task, signal } => {
+                write!(f, "{} was stopped by {}", task, signal)
+            }
+            HastyError::TimedOut { task, timeout } => {
+                write!(f, "{} timed out after {}s", task, timeout.as_secs())
+            }
+            HastyError::Git(source) => {
+                write!(f, "unable to determine changed packages: {}", source)
+            }
+            HastyError::Watch(source) => write!(f, "unable to watch for changes: {}", source),
+            HastyError::Output(source) => write!(f, "unable to write to stdout: {}", source),
+            HastyError::Signal(source) => write!(f, "unable to listen for signals: {}", source),
+        }
+    }
+}
+
+impl error::Error for HastyError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            HastyError::Io { source, .. } => Some(source),
+            HastyError::Json { source, .. } => Some(source),
+            HastyError::Yaml { source, .. } => Some(source),
+            HastyError::Glob { source, .. } => Some(source),
+            HastyError::Process { source, .. } => Some(source),
+            HastyError::Git(source) => Some(source),
+            HastyError::Watch(source) => Some(source),
+            HastyError::Output(source) => Some(source),
+            HastyError::Signal(source) => Some(source),
+            HastyError::InvalidConfig { .. }
+            | HastyError::UnknownTask { .. }
+            | HastyError::MissingScript { .. }
+            | HastyError::PersistentDependency { .. }
+            | HastyError::Interrupted { .. }
+            | HastyError::TimedOut { .. }
+            | HastyError::TaskCycle(_)
+            | HastyError::PackageCycle(_) => None,
+        }
+    }
+}
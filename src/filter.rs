@@ -0,0 +1,305 @@
+use daggy::{
+    petgraph::visit::{Dfs, Reversed},
+    Dag, NodeIndex,
+};
+use glob::Pattern;
+use serde::Deserialize;
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Component, Path, PathBuf},
+    str::FromStr,
+};
+
+use crate::package_json::PackageJSON;
+
+static DEPENDENCIES_SUFFIX: &str = "...";
+static DEPENDENTS_PREFIX: &str = "...";
+static EXCLUDE_PREFIX: &str = "!";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SelectorPattern {
+    /// A package name, or a glob over package names such as `@scope/*`
+    Name(Pattern),
+    /// A directory relative to the workspace root such as `./packages/a`, which may be a glob
+    Dir(Pattern),
+}
+
+/// A pnpm-style package selector: `pkg`, `@scope/*`, `./packages/a`, `pkg...` to include the
+/// package's dependencies, `...pkg` to include its dependents, and `!pkg` to exclude packages.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Selector {
+    pub pattern: SelectorPattern,
+    pub include_dependencies: bool,
+    pub include_dependents: bool,
+    pub exclude: bool,
+}
+
+impl FromStr for Selector {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let mut rest = value;
+
+        let exclude = rest.starts_with(EXCLUDE_PREFIX);
+        if exclude {
+            rest = &rest[EXCLUDE_PREFIX.len()..];
+        }
+
+        let include_dependents = rest.starts_with(DEPENDENTS_PREFIX);
+        if include_dependents {
+            rest = &rest[DEPENDENTS_PREFIX.len()..];
+        }
+
+        let include_dependencies = rest.ends_with(DEPENDENCIES_SUFFIX);
+        if include_dependencies {
+            rest = &rest[..rest.len() - DEPENDENCIES_SUFFIX.len()];
+        }
+
+        if rest.is_empty() {
+            return Err(format!("invalid filter: {}", value));
+        }
+
+        let pattern = if rest.starts_with('.') || rest.starts_with('/') {
+            let dir = rest.trim_start_matches("./").trim_end_matches('/');
+            let dir = if dir == "." { "" } else { dir };
+
+            SelectorPattern::Dir(Pattern::new(dir).map_err(|e| format!("{}: {}", value, e))?)
+        } else {
+            SelectorPattern::Name(Pattern::new(rest).map_err(|e| format!("{}: {}", value, e))?)
+        };
+
+        Ok(Selector {
+            pattern,
+            include_dependencies,
+            include_dependents,
+            exclude,
+        })
+    }
+}
+
+impl TryFrom<String> for Selector {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Selector::from_str(&value)
+    }
+}
+
+impl Selector {
+    fn matches(&self, package: &PackageJSON, root_dir: &Path) -> bool {
+        match &self.pattern {
+            SelectorPattern::Name(pattern) => pattern.matches(&package.name),
+            SelectorPattern::Dir(pattern) => {
+                let dir = match &package.dir {
+                    Some(dir) => without_cur_dir(dir),
+                    None => return false,
+                };
+
+                match dir.strip_prefix(without_cur_dir(root_dir)) {
+                    Ok(relative) => pattern.matches_path(relative),
+                    Err(_) => false,
+                }
+            }
+        }
+    }
+}
+
+// Drops `.` components, since globbed workspace directories lose the leading `./` of the root
+pub(crate) fn without_cur_dir(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|c| *c != Component::CurDir)
+        .collect()
+}
+
+/// Resolves the names of the workspace packages matched by `selectors`. Packages matched by any
+/// non-excluding selector are selected, minus those matched by an excluding one. When only
+/// excluding selectors are given, every package starts out selected.
+pub fn select_packages(
+    selectors: &[Selector],
+    workspaces: &[PackageJSON],
+    package_graph: &Dag<String, u32, u32>,
+    package_nodes: &HashMap<String, NodeIndex>,
+    root_dir: &Path,
+) -> HashSet<String> {
+    let workspace_names = workspaces
+        .iter()
+        .map(|ws| ws.name.clone())
+        .collect::<HashSet<String>>();
+
+    let mut selected = if selectors.iter().all(|s| s.exclude) {
+        workspace_names.clone()
+    } else {
+        HashSet::new()
+    };
+    let mut excluded = HashSet::new();
+
+    for selector in selectors {
+        let mut matched = HashSet::new();
+
+        for ws in workspaces
+            .iter()
+            .filter(|ws| selector.matches(ws, root_dir))
+        {
+            matched.insert(ws.name.clone());
+
+            let node = match package_nodes.get(&ws.name) {
+                Some(node) => *node,
+                None => continue,
+            };
+
+            // edges point from a dependency to its dependent, so dependencies are found by
+            // walking the graph backwards
+            if selector.include_dependencies {
+                let graph = Reversed(package_graph.graph());
+                let mut dfs = Dfs::new(graph, node);
+                while let Some(dep) = dfs.next(graph) {
+                    matched.insert(package_graph[dep].clone());
+                }
+            }
+
+            if selector.include_dependents {
+                let graph = package_graph.graph();
+                let mut dfs = Dfs::new(graph, node);
+                while let Some(dependent) = dfs.next(graph) {
+                    matched.insert(package_graph[dependent].clone());
+                }
+            }
+        }
+
+        if selector.exclude {
+            excluded.extend(matched);
+        } else {
+            selected.extend(matched);
+        }
+    }
+
+    selected
+        .into_iter()
+        .filter(|name| workspace_names.contains(name) && !excluded.contains(name))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn selector(value: &str) -> Selector {
+        Selector::from_str(value).unwrap()
+    }
+
+    fn name(pattern: &str) -> SelectorPattern {
+        SelectorPattern::Name(Pattern::new(pattern).unwrap())
+    }
+
+    fn dir(pattern: &str) -> SelectorPattern {
+        SelectorPattern::Dir(Pattern::new(pattern).unwrap())
+    }
+
+    #[test]
+    fn parses_selectors() {
+        let cases = [
+            // (selector, pattern, dependencies, dependents, exclude)
+            ("pkg", name("pkg"), false, false, false),
+            ("pkg...", name("pkg"), true, false, false),
+            ("...pkg", name("pkg"), false, true, false),
+            ("...pkg...", name("pkg"), true, true, false),
+            ("!pkg", name("pkg"), false, false, true),
+            ("!...pkg", name("pkg"), false, true, true),
+            ("@scope/*", name("@scope/*"), false, false, false),
+            ("./packages/a", dir("packages/a"), false, false, false),
+            ("./packages/*/", dir("packages/*"), false, false, false),
+            ("./packages/a...", dir("packages/a"), true, false, false),
+            (".", dir(""), false, false, false),
+        ];
+
+        for (value, pattern, include_dependencies, include_dependents, exclude) in cases {
+            assert_eq!(
+                selector(value),
+                Selector {
+                    pattern,
+                    include_dependencies,
+                    include_dependents,
+                    exclude,
+                },
+                "{}",
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_empty_and_invalid_selectors() {
+        for value in ["", "...", "!", "!...", "[a"] {
+            assert!(Selector::from_str(value).is_err(), "{}", value);
+        }
+    }
+
+    // A workspace where `app` depends on `@scope/ui`, which depends on `@scope/utils`, and `docs`
+    // is on its own
+    fn workspace() -> (
+        Vec<PackageJSON>,
+        Dag<String, u32, u32>,
+        HashMap<String, NodeIndex>,
+    ) {
+        let packages = [
+            ("root", "."),
+            ("app", "./apps/app"),
+            ("docs", "./apps/docs"),
+            ("@scope/ui", "./packages/ui"),
+            ("@scope/utils", "./packages/utils"),
+        ];
+
+        let workspaces = packages
+            .iter()
+            .map(|(name, dir)| {
+                let mut pkg: PackageJSON =
+                    serde_json::from_str(&format!(r#"{{ "name": "{}" }}"#, name)).unwrap();
+                pkg.dir = Some(PathBuf::from(dir));
+                pkg
+            })
+            .collect::<Vec<PackageJSON>>();
+
+        let mut graph = Dag::new();
+        let mut nodes = HashMap::new();
+        for (name, _) in packages {
+            nodes.insert(name.to_string(), graph.add_node(name.to_string()));
+        }
+        for (from, to) in [("@scope/utils", "@scope/ui"), ("@scope/ui", "app")] {
+            graph.add_edge(nodes[from], nodes[to], 0).unwrap();
+        }
+
+        (workspaces, graph, nodes)
+    }
+
+    fn select(selectors: &[&str]) -> Vec<String> {
+        let (workspaces, graph, nodes) = workspace();
+        let selectors = selectors.iter().map(|s| selector(s)).collect::<Vec<_>>();
+
+        let mut selected = select_packages(&selectors, &workspaces, &graph, &nodes, Path::new("."))
+            .into_iter()
+            .collect::<Vec<String>>();
+        selected.sort();
+        selected
+    }
+
+    #[test]
+    fn selects_packages_from_the_graph() {
+        let cases: [(&[&str], &[&str]); 10] = [
+            (&["app"], &["app"]),
+            (&["app..."], &["@scope/ui", "@scope/utils", "app"]),
+            (&["...@scope/utils"], &["@scope/ui", "@scope/utils", "app"]),
+            (&["@scope/*"], &["@scope/ui", "@scope/utils"]),
+            (&["./apps/*"], &["app", "docs"]),
+            (&["./packages/ui..."], &["@scope/ui", "@scope/utils"]),
+            (&["!docs", "!root"], &["@scope/ui", "@scope/utils", "app"]),
+            (&["app...", "!@scope/utils"], &["@scope/ui", "app"]),
+            (&["app", "docs"], &["app", "docs"]),
+            (&["missing"], &[]),
+        ];
+
+        for (selectors, expected) in cases {
+            assert_eq!(select(selectors), expected, "{:?}", selectors);
+        }
+    }
+}
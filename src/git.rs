@@ -0,0 +1,133 @@
+use daggy::{petgraph::visit::Dfs, Dag, NodeIndex};
+use std::{
+    collections::{HashMap, HashSet},
+    ffi::OsStr,
+    io,
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use crate::{filter::without_cur_dir, package_json::PackageJSON, CONFIG_FILE_NAME};
+
+// Files at the workspace root that affect every package when they change
+static GLOBAL_FILES: [&str; 10] = [
+    CONFIG_FILE_NAME,
+    "package.json",
+    "package-lock.json",
+    "npm-shrinkwrap.json",
+    "yarn.lock",
+    "pnpm-lock.yaml",
+    "pnpm-workspace.yaml",
+    "bun.lock",
+    "bun.lockb",
+    "lerna.json",
+];
+
+/// Lists the files under `root_dir` that changed since the commit where the current branch forked
+/// from `since`, including uncommitted and untracked changes, so commits that only landed on
+/// `since` don't count. Paths are relative to `root_dir`.
+pub fn changed_files(root_dir: &Path, since: &str) -> io::Result<Vec<PathBuf>> {
+    let merge_base = git(root_dir, &["merge-base", since, "HEAD"])?;
+    let merge_base = String::from_utf8_lossy(&merge_base);
+
+    // `-z` keeps git from quoting paths with unusual characters, such as non-ASCII ones
+    let mut files = paths(git(
+        root_dir,
+        &["diff", "--name-only", "-z", "--relative", merge_base.trim()],
+    )?);
+    files.extend(paths(git(
+        root_dir,
+        &["ls-files", "-z", "--others", "--exclude-standard"],
+    )?));
+
+    files.sort();
+    files.dedup();
+
+    Ok(files)
+}
+
+/// Maps changed files to the workspace packages that own them, plus every package that depends on
+/// those packages. A change to a global file such as the lockfile or hasty.json affects every
+/// package.
+pub fn affected_packages(
+    changed_files: &[PathBuf],
+    workspaces: &[PackageJSON],
+    package_graph: &Dag<String, u32, u32>,
+    package_nodes: &HashMap<String, NodeIndex>,
+    root_dir: &Path,
+) -> HashSet<String> {
+    let all_packages = workspaces.iter().map(|ws| ws.name.clone());
+
+    if changed_files
+        .iter()
+        .any(|f| GLOBAL_FILES.iter().any(|g| f == Path::new(g)))
+    {
+        return all_packages.collect();
+    }
+
+    let root_dir = without_cur_dir(root_dir);
+
+    // pair each package with its directory relative to the root, deepest first so nested
+    // packages win over the packages that contain them
+    let mut package_dirs = workspaces
+        .iter()
+        .filter_map(|ws| {
+            let dir = without_cur_dir(ws.dir.as_deref()?);
+            let relative = dir.strip_prefix(&root_dir).ok()?.to_path_buf();
+            Some((relative, &ws.name))
+        })
+        .collect::<Vec<(PathBuf, &String)>>();
+    package_dirs.sort_by_key(|(dir, _)| std::cmp::Reverse(dir.components().count()));
+
+    let mut affected = HashSet::new();
+
+    for file in changed_files {
+        let owner = package_dirs.iter().find(|(dir, _)| file.starts_with(dir));
+
+        let name = match owner {
+            Some((_, name)) => *name,
+            None => continue,
+        };
+
+        if !affected.insert(name.clone()) {
+            continue;
+        }
+
+        if let Some(&node) = package_nodes.get(name) {
+            let graph = package_graph.graph();
+            let mut dfs = Dfs::new(graph, node);
+            while let Some(dependent) = dfs.next(graph) {
+                affected.insert(package_graph[dependent].clone());
+            }
+        }
+    }
+
+    let all_packages = all_packages.collect::<HashSet<String>>();
+    affected.retain(|name| all_packages.contains(name));
+
+    affected
+}
+
+fn git(dir: &Path, args: &[&str]) -> io::Result<Vec<u8>> {
+    let output = Command::new("git").current_dir(dir).args(args).output()?;
+
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+
+    Ok(output.stdout)
+}
+
+// Splits the NUL separated paths that git prints with `-z`
+fn paths(output: Vec<u8>) -> Vec<PathBuf> {
+    output
+        .split(|b| *b == 0)
+        .filter(|p| !p.is_empty())
+        .map(|p| PathBuf::from(OsStr::from_bytes(p)))
+        .collect()
+}
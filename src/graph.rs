@@ -0,0 +1,115 @@
+use clap::ValueEnum;
+use daggy::Dag;
+use serde::{Deserialize, Serialize};
+
+// Placeholder node the package graph is seeded with, which isn't a real package
+static ROOT_NODE: &str = "__ROOT__";
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum GraphFormat {
+    Dot,
+    Mermaid,
+    Json,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum GraphKind {
+    Task,
+    Package,
+}
+
+#[derive(Debug, Serialize)]
+struct JsonGraph<'a> {
+    nodes: Vec<&'a str>,
+    edges: Vec<JsonEdge<'a>>,
+}
+
+#[derive(Debug, Serialize)]
+struct JsonEdge<'a> {
+    from: &'a str,
+    to: &'a str,
+}
+
+/// Renders a task or package graph. Edges point from a task or package to the ones that depend
+/// on it, so they follow the order things run in. Nodes and edges are sorted by name, so the
+/// output is stable between runs.
+pub fn render(graph: &Dag<String, u32, u32>, format: GraphFormat) -> String {
+    let (nodes, edges) = sorted_nodes_and_edges(graph);
+
+    match format {
+        GraphFormat::Dot => {
+            let mut out = String::from("digraph {\n");
+            for node in &nodes {
+                out.push_str(&format!("    \"{}\";\n", escape_dot(node)));
+            }
+            for (from, to) in &edges {
+                out.push_str(&format!(
+                    "    \"{}\" -> \"{}\";\n",
+                    escape_dot(from),
+                    escape_dot(to)
+                ));
+            }
+            out.push_str("}\n");
+            out
+        }
+        GraphFormat::Mermaid => {
+            // mermaid ids can't contain characters like `#` or `@`, so nodes get a numeric id
+            // and their name as the label
+            let id = |name: &str| nodes.binary_search(&name).unwrap_or_default();
+
+            let mut out = String::from("graph TD\n");
+            for (i, node) in nodes.iter().enumerate() {
+                out.push_str(&format!("    n{}[\"{}\"]\n", i, escape_mermaid(node)));
+            }
+            for (from, to) in &edges {
+                out.push_str(&format!("    n{} --> n{}\n", id(from), id(to)));
+            }
+            out
+        }
+        GraphFormat::Json => {
+            let graph = JsonGraph {
+                nodes,
+                edges: edges
+                    .into_iter()
+                    .map(|(from, to)| JsonEdge { from, to })
+                    .collect(),
+            };
+
+            // a graph of strings always serializes
+            let mut out = serde_json::to_string_pretty(&graph).unwrap_or_default();
+            out.push('\n');
+            out
+        }
+    }
+}
+
+fn sorted_nodes_and_edges(graph: &Dag<String, u32, u32>) -> (Vec<&str>, Vec<(&str, &str)>) {
+    let mut nodes = graph
+        .raw_nodes()
+        .iter()
+        .map(|n| n.weight.as_str())
+        .filter(|n| *n != ROOT_NODE)
+        .collect::<Vec<&str>>();
+    nodes.sort();
+    nodes.dedup();
+
+    let mut edges = graph
+        .raw_edges()
+        .iter()
+        .map(|e| (graph[e.source()].as_str(), graph[e.target()].as_str()))
+        .collect::<Vec<(&str, &str)>>();
+    edges.sort();
+    edges.dedup();
+
+    (nodes, edges)
+}
+
+fn escape_dot(label: &str) -> String {
+    label.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn escape_mermaid(label: &str) -> String {
+    label.replace('"', "#quot;")
+}
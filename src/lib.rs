@@ -1,26 +1,68 @@
+pub mod cache;
+pub mod error;
+pub mod filter;
+pub mod git;
+pub mod graph;
 pub mod logger;
 pub mod options;
+pub mod package_config;
 pub mod package_json;
-
+pub mod package_manager;
+pub mod plan;
+pub mod readiness;
+pub mod scheduler;
+pub mod shell;
+pub mod shutdown;
+pub mod version;
+pub mod watch;
+
+use cache::Cache;
+use clap::ValueEnum;
 use daggy::{
-    petgraph::visit::{IntoNodeIdentifiers, Topo},
+    petgraph::visit::{Dfs, Topo},
     Dag, NodeIndex, Walker,
 };
-use futures::future::join_all;
-use log::{error, info};
+use error::Result;
+use filter::Selector;
+use futures::{channel::oneshot, future::join_all};
+use log::{error, info, warn};
+use package_config::{load_package_configs, PackageConfig};
 use package_json::{find_workspaces, PackageJSON};
+use package_manager::PackageManager;
+use plan::TaskPlan;
+use readiness::Readiness;
+use scheduler::{Scheduler, TaskExecutor};
 use serde::Deserialize;
-use std::process::Stdio;
-use std::{collections::HashMap, env, fs, path::PathBuf, time::SystemTime};
+use shutdown::{ProcessGroup, Shutdown, Signal};
+use std::process::{ExitStatus, Stdio};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    env,
+    ffi::OsString,
+    fs,
+    future::Future,
+    io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
 use tokio::{
     io::{AsyncBufReadExt, AsyncRead, BufReader},
     process::{Child, Command},
-    sync::watch::{self, Receiver},
+    sync::mpsc,
+    task::JoinHandle,
 };
-use urlencoding::encode;
 
-static CONFIG_FILE_NAME: &str = "hasty.json";
+pub use error::HastyError;
+
+pub(crate) static CONFIG_FILE_NAME: &str = "hasty.json";
 pub static TOPOLOGICAL_DEP_PREFIX: &str = "^";
+pub static PACKAGE_TASK_SEPARATOR: char = '#';
+// How many of a timed out task's last output lines are repeated after it is stopped by default
+pub static TIMED_OUT_OUTPUT_LINES: usize = 20;
+// How long a script's stdio may stay open after it exits, e.g. held by a process it started in the
+// background, before hasty stops reading it
+static PIPE_DRAIN_TIMEOUT: Duration = Duration::from_millis(100);
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct CommandConfig {
@@ -28,118 +70,255 @@ pub struct CommandConfig {
     pub dependencies: Option<Vec<String>>,
     pub files: Option<Vec<String>>,
     pub output: Option<Vec<String>>,
+    /// The number of seconds the task may run before it is stopped and reported as timed out
+    pub timeout: Option<u64>,
+    /// Whether the task keeps running until it is stopped, like a dev server. Persistent tasks
+    /// are never cached, and only tasks with a readiness probe may be depended on.
+    #[serde(default)]
+    pub persistent: bool,
+    /// When set, the task's dependents start as soon as the condition holds instead of when the
+    /// task exits
+    pub readiness: Option<Readiness>,
 }
 
 #[derive(Debug, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
 pub struct Config {
     pub pipeline: HashMap<String, CommandConfig>,
+    pub package_manager: Option<PackageManager>,
+    /// Whether a dependency cycle between workspace packages fails the run or is only reported
+    #[serde(default)]
+    pub package_cycles: CycleBehavior,
+}
+
+impl Config {
+    /// The pipeline entry for `task` in `package`: a `package#task` entry when there is one,
+    /// otherwise the generic `task` entry
+    pub fn task_config(&self, package: &str, task: &str) -> Option<&CommandConfig> {
+        self.pipeline
+            .get(&make_script_id(package, task))
+            .or_else(|| self.pipeline.get(task))
+    }
+
+    /// Whether the pipeline has a generic entry for `task` or an entry scoped to some package
+    pub fn defines_task(&self, task: &str) -> bool {
+        self.task_names().any(|name| name == task)
+    }
+
+    /// The names of the pipeline's tasks, with the package of package-scoped entries removed.
+    /// A task may be listed more than once.
+    pub fn task_names(&self) -> impl Iterator<Item = &str> {
+        self.pipeline
+            .keys()
+            .map(|key| split_script_id(key).map_or(key.as_str(), |(_, task)| task))
+    }
+
+    /// Checks that every entry's `command` is the task it is keyed by. Tasks are identified by
+    /// their command, so `"a#build": { "command": "compile" }` would turn into `a#compile`, and
+    /// everything depending on `a#build` would silently lose that dependency.
+    pub fn validate(&self, path: &Path) -> Result<()> {
+        for (key, config) in &self.pipeline {
+            let task = split_script_id(key).map_or(key.as_str(), |(_, task)| task);
+
+            if let Err(message) = check_command(task, &config.command) {
+                return Err(HastyError::InvalidConfig {
+                    path: path.to_path_buf(),
+                    message: format!("{}: {}", key, message),
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Checks that a pipeline entry for `task` runs the script of the same name
+pub(crate) fn check_command(task: &str, command: &str) -> std::result::Result<(), String> {
+    if command == task {
+        Ok(())
+    } else {
+        Err(format!(
+            "\"command\" must be \"{}\", the name of the task, not \"{}\"",
+            task, command
+        ))
+    }
+}
+
+#[derive(Debug, Default, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum CycleBehavior {
+    #[default]
+    Warn,
+    Error,
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum ScriptStatus {
-    Waiting,
-    Ready,
-    Running,
     Finished,
     Error,
+    Skipped,
+    /// Stopped because hasty received a signal
+    Interrupted,
+    /// Stopped because it ran for longer than its timeout
+    TimedOut,
+    /// Stopped, or never started, because watch mode is running it again
+    Cancelled,
+}
+
+/// The final status of every task that was part of a run
+#[derive(Debug, Default)]
+pub struct ExecutionSummary {
+    pub succeeded: Vec<String>,
+    pub failed: Vec<String>,
+    pub skipped: Vec<String>,
+    pub interrupted: Vec<String>,
+    pub timed_out: Vec<String>,
+    /// Tasks that were never started because hasty received a signal
+    pub not_started: Vec<String>,
+    /// Tasks that watch mode stopped in order to run them again
+    pub cancelled: Vec<String>,
+}
+
+impl ExecutionSummary {
+    pub fn is_success(&self) -> bool {
+        self.failed.is_empty()
+            && self.interrupted.is_empty()
+            && self.timed_out.is_empty()
+            && self.not_started.is_empty()
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct Script {
-    pub status: ScriptStatus,
     pub command: String,
     pub package_name: String,
     config: CommandConfig,
     pub dir: PathBuf,
+    // receives every line of output from the processes the script spawns
+    lines: Option<mpsc::UnboundedSender<String>>,
 }
 
 impl Script {
-    pub fn new(config: CommandConfig, dir: &PathBuf, package_name: &str) -> Self {
+    pub fn new(config: CommandConfig, dir: &Path, package_name: &str) -> Self {
         let name = config.command.clone();
 
-        let mut status = ScriptStatus::Ready;
-        if let Some(ref _deps) = config.dependencies {
-            status = ScriptStatus::Waiting;
-        }
-
         Script {
             config,
             package_name: package_name.to_string(),
             dir: dir.into(),
-            command: name.to_string(),
-            status,
+            command: name,
+            lines: None,
         }
     }
 
-    pub fn execute(&mut self) -> Child {
-        self.status = ScriptStatus::Running;
+    pub fn execute(&mut self, package_manager: PackageManager) -> Result<ScriptProcess> {
+        let mut command = Command::new(package_manager.binary());
+
+        command.args(package_manager.run_args(&self.config.command));
+
+        self.spawn(command)
+    }
+
+    /// Runs a script body directly through `sh -c` with the given environment, bypassing the
+    /// package manager
+    pub fn execute_in_shell(
+        &mut self,
+        body: &str,
+        env: Vec<(String, OsString)>,
+    ) -> Result<ScriptProcess> {
+        let mut command = Command::new("sh");
+
+        command.arg("-c").arg(body).envs(env);
 
-        let mut command = Command::new("npm");
+        self.spawn(command)
+    }
+
+    fn spawn(&mut self, mut command: Command) -> Result<ScriptProcess> {
+        // each script leads its own process group, so signals reach everything it starts. Only the
+        // foreground group may read from the terminal, so scripts get no stdin.
+        unsafe {
+            command.pre_exec(|| match libc::setpgid(0, 0) {
+                0 => Ok(()),
+                _ => Err(io::Error::last_os_error()),
+            });
+        }
 
         let mut child = command
             .current_dir(&self.dir)
-            .arg("run")
-            .arg(self.config.command.clone())
+            .stdin(Stdio::null())
             .stdout(Stdio::piped())
             .stderr(Stdio::piped())
+            .kill_on_drop(true)
             .spawn()
-            .expect(&format!(
-                "failed to spawn command {}",
-                &self.config.command.clone()
-            ));
+            .map_err(|source| HastyError::Process {
+                task: self.id(),
+                source,
+            })?;
 
+        // both are always present, since they were configured as piped above
         let stdout = child.stdout.take().unwrap();
         let stderr = child.stderr.take().unwrap();
 
-        let script_id = self.id();
-        let script_id_err = self.id();
+        let output = Arc::new(Mutex::new(vec![]));
 
-        pipe_child_stdio(stdout, script_id);
-        pipe_child_stdio(stderr, script_id_err);
+        let pipes = vec![
+            pipe_child_stdio(stdout, self.id(), output.clone(), self.lines.clone()),
+            pipe_child_stdio(stderr, self.id(), output.clone(), self.lines.clone()),
+        ];
 
-        child
+        Ok(ScriptProcess {
+            group: ProcessGroup::new(child.id()),
+            child,
+            output,
+            pipes,
+        })
+    }
+
+    /// Sends each line the script prints to `lines` as it is read, in addition to logging it
+    pub fn forward_output(&mut self, lines: mpsc::UnboundedSender<String>) {
+        self.lines = Some(lines);
+    }
+
+    pub fn config(&self) -> &CommandConfig {
+        &self.config
     }
 
     pub fn has_dependencies(&self) -> bool {
         if let Some(deps) = &self.config.dependencies {
-            return deps.len() > 0;
+            return !deps.is_empty();
         }
 
         false
     }
 
     pub fn dependencies(&self) -> Option<Vec<String>> {
-        match self.config.dependencies.clone() {
-            Some(deps) => Some(
-                deps.iter()
-                    .filter(|d| !d.starts_with(&TOPOLOGICAL_DEP_PREFIX))
-                    .map(|d| {
-                        if d.starts_with(&TOPOLOGICAL_DEP_PREFIX) == false {
-                            return String::from(d);
-                        }
-                        return d.replace(TOPOLOGICAL_DEP_PREFIX, "");
-                    })
-                    .collect(),
-            ),
-            None => None,
-        }
+        self.config.dependencies.as_ref().map(|deps| {
+            deps.iter()
+                .filter(|d| !d.starts_with(TOPOLOGICAL_DEP_PREFIX))
+                .filter(|d| split_script_id(d).is_none())
+                .map(String::from)
+                .collect()
+        })
+    }
+
+    /// Dependencies on a task in a specific package, written as `package#task`
+    pub fn package_dependencies(&self) -> Option<Vec<(String, String)>> {
+        self.config.dependencies.as_ref().map(|deps| {
+            deps.iter()
+                .filter(|d| !d.starts_with(TOPOLOGICAL_DEP_PREFIX))
+                .filter_map(|d| split_script_id(d))
+                .map(|(package, task)| (String::from(package), String::from(task)))
+                .collect()
+        })
     }
 
     pub fn topological_dependencies(&self) -> Option<Vec<String>> {
-        match self.config.dependencies.clone() {
-            Some(deps) => Some(
-                deps.iter()
-                    .filter(|d| d.starts_with(&TOPOLOGICAL_DEP_PREFIX))
-                    .map(|d| {
-                        if d.starts_with(&TOPOLOGICAL_DEP_PREFIX) == false {
-                            return String::from(d);
-                        }
-                        return d.replace(TOPOLOGICAL_DEP_PREFIX, "");
-                    })
-                    .collect(),
-            ),
-            None => None,
-        }
+        self.config.dependencies.as_ref().map(|deps| {
+            deps.iter()
+                .filter(|d| d.starts_with(TOPOLOGICAL_DEP_PREFIX))
+                .map(|d| d.replace(TOPOLOGICAL_DEP_PREFIX, ""))
+                .collect()
+        })
     }
 
     pub fn id(&self) -> String {
@@ -153,58 +332,327 @@ impl PartialEq for Script {
     }
 }
 
+/// Why a script's process was stopped before it exited on its own
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stop {
+    Signal(Signal),
+    TimedOut,
+}
+
+/// A running script's child process along with the output captured from its stdio.
+pub struct ScriptProcess {
+    child: Child,
+    group: ProcessGroup,
+    output: Arc<Mutex<Vec<String>>>,
+    pipes: Vec<JoinHandle<()>>,
+}
+
+impl ScriptProcess {
+    /// Waits for the process to exit and its stdio to be drained, returning the exit status and
+    /// the combined stdout/stderr lines in the order they were received. Output that arrives more
+    /// than a moment after the process exits is dropped. When `shutdown` receives
+    /// a signal first, the signal is forwarded to the script's process group, and when `deadline`
+    /// passes first, the group is sent SIGTERM. Either way the group is killed if it is still
+    /// running after `grace_period`, and the reason it was stopped is returned as well.
+    pub async fn wait(
+        mut self,
+        shutdown: &Shutdown,
+        deadline: Option<tokio::time::Instant>,
+        grace_period: Duration,
+    ) -> (io::Result<ExitStatus>, Vec<String>, Option<Stop>) {
+        let mut pipes = std::mem::take(&mut self.pipes);
+        let exited = async {
+            let status = self.child.wait().await;
+
+            let drained = join_all(pipes.iter_mut());
+            if tokio::time::timeout(PIPE_DRAIN_TIMEOUT, drained)
+                .await
+                .is_err()
+            {
+                pipes.iter().for_each(JoinHandle::abort);
+            }
+
+            status
+        };
+        tokio::pin!(exited);
+
+        let timed_out = async {
+            match deadline {
+                Some(deadline) => tokio::time::sleep_until(deadline).await,
+                None => std::future::pending().await,
+            }
+        };
+        let stopped = async {
+            tokio::select! {
+                signal = shutdown.received() => Stop::Signal(signal),
+                () = timed_out => Stop::TimedOut,
+            }
+        };
+
+        let (status, stop) = tokio::select! {
+            status = &mut exited => (status, None),
+            stop = stopped => {
+                match stop {
+                    Stop::Signal(signal) => self.group.signal(signal),
+                    Stop::TimedOut => self.group.signal(Signal::Terminate),
+                }
+
+                // a second signal skips whatever is left of the grace period
+                let out_of_time = async {
+                    tokio::select! {
+                        () = tokio::time::sleep(grace_period) => {}
+                        () = shutdown.forced() => {}
+                    }
+                };
+                let status = tokio::select! {
+                    status = &mut exited => status,
+                    () = out_of_time => {
+                        self.group.kill();
+                        exited.await
+                    }
+                };
+
+                (status, Some(stop))
+            }
+        };
+
+        self.group.release();
+
+        let output = std::mem::take(&mut *self.output.lock().unwrap());
+
+        (status, output, stop)
+    }
+}
+
+/// How a task's script is started
+#[derive(Debug, Default, Deserialize, Clone, Copy, PartialEq, Eq, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum ExecutionMode {
+    /// Through the package manager, e.g. `npm run build`
+    #[default]
+    PackageManager,
+    /// The script body from package.json through `sh -c`, running `pre`/`post` hooks directly
+    Shell,
+}
+
+/// Options that control how the engine schedules tasks
+#[derive(Debug, Clone)]
+pub struct EngineOptions {
+    /// Keep running every task whose dependencies succeeded after a failure, instead of skipping
+    /// everything that hasn't started yet
+    pub continue_on_error: bool,
+    /// The maximum number of tasks that may run at the same time
+    pub concurrency: usize,
+    /// Overrides the package manager from hasty.json and the one detected from the workspace
+    pub package_manager: Option<PackageManager>,
+    /// Whether scripts run through the package manager or directly through a shell
+    pub execution_mode: ExecutionMode,
+    /// Stops the run when hasty receives a signal
+    pub shutdown: Shutdown,
+    /// How long a script gets to exit after a signal is forwarded to it before it is killed
+    pub grace_period: Duration,
+    /// How long tasks without their own `timeout` may run, persistent tasks excepted
+    pub default_timeout: Option<Duration>,
+    /// How many of a timed out task's last output lines are repeated after it is stopped
+    pub timed_out_output_lines: usize,
+}
+
+impl Default for EngineOptions {
+    fn default() -> Self {
+        EngineOptions {
+            continue_on_error: false,
+            concurrency: options::default_concurrency(),
+            package_manager: None,
+            execution_mode: ExecutionMode::default(),
+            shutdown: Shutdown::never(),
+            grace_period: Duration::from_secs(10),
+            default_timeout: None,
+            timed_out_output_lines: TIMED_OUT_OUTPUT_LINES,
+        }
+    }
+}
+
 pub struct Engine {
     called_scripts: Vec<String>,
+    options: EngineOptions,
     dir: PathBuf,
     config: Config,
     task_graph: Dag<String, u32, u32>,
     package_graph: Dag<String, u32, u32>,
+    task_nodes: HashMap<String, NodeIndex>,
+    package_nodes: HashMap<String, NodeIndex>,
     scripts: HashMap<String, Script>,
     deps: Vec<(String, String)>,
     workspaces: Vec<PackageJSON>,
+    workspace_indexes: HashMap<String, usize>,
+    package_configs: HashMap<String, PackageConfig>,
+    external_dependencies: HashMap<String, BTreeMap<String, String>>,
+    selected_packages: Option<HashSet<String>>,
+    package_manager: PackageManager,
+    cache: Cache,
 }
 
 impl Engine {
-    pub fn new(config: Config, dir: &PathBuf, called_scripts: Vec<String>) -> Self {
-        let workspaces = find_workspaces(&dir);
+    pub fn new(
+        config: Config,
+        dir: &Path,
+        called_scripts: Vec<String>,
+        options: EngineOptions,
+    ) -> Result<Self> {
+        config.validate(&dir.join(CONFIG_FILE_NAME))?;
+
+        let workspaces = find_workspaces(dir)?;
+        let package_configs = load_package_configs(&workspaces, dir, &config)?;
+        let workspace_indexes = workspaces
+            .iter()
+            .enumerate()
+            .map(|(i, ws)| (ws.name.clone(), i))
+            .collect();
         let mut package_graph = Dag::<String, u32, u32>::new();
 
         package_graph.add_node(String::from("__ROOT__"));
 
-        Engine {
+        let package_manager = options
+            .package_manager
+            .or(config.package_manager)
+            .unwrap_or_else(|| {
+                let root_package = workspaces.iter().find(|ws| ws.dir.as_deref() == Some(dir));
+                PackageManager::detect(dir, root_package)
+            });
+
+        Ok(Engine {
             called_scripts,
+            options,
             dir: dir.into(),
             config,
             package_graph,
             task_graph: Dag::<String, u32, u32>::new(),
+            task_nodes: HashMap::new(),
+            package_nodes: HashMap::new(),
             scripts: HashMap::<String, Script>::new(),
             deps: Vec::new(),
             workspaces,
-        }
+            workspace_indexes,
+            package_configs,
+            external_dependencies: HashMap::new(),
+            selected_packages: None,
+            package_manager,
+            cache: Cache::new(dir),
+        })
     }
 
-    pub fn add_deps_to_graph(&mut self) {
+    pub fn add_deps_to_graph(&mut self) -> Result<()> {
+        let mut edges = vec![];
+
         for (from_id, to_id) in self.deps.iter() {
-            let from_index = find_node_index(&self.task_graph, String::from(from_id));
-            let to_index = find_node_index(&self.task_graph, String::from(to_id));
+            let (from, to) = match (self.task_nodes.get(from_id), self.task_nodes.get(to_id)) {
+                (Some(from), Some(to)) => (*from, *to),
+                _ => continue,
+            };
 
-            if let (Some(from), Some(to)) = (from_index, to_index) {
-                if self.task_graph.add_edge(from, to, 0).is_err() {
-                    panic!("Cycle detected in the task graph: {} -> {}", from_id, to_id);
-                }
+            // a persistent task never finishes, so without a readiness probe its dependents would
+            // wait forever
+            if self
+                .scripts
+                .get(from_id)
+                .is_some_and(|s| s.config.persistent && s.config.readiness.is_none())
+            {
+                return Err(HastyError::PersistentDependency {
+                    task: from_id.clone(),
+                    dependent: to_id.clone(),
+                });
             }
+
+            edges.push((from, to));
         }
+
+        if add_edges_checked_once(&mut self.task_graph, &edges, 0) {
+            return Ok(());
+        }
+
+        for (from, to) in edges {
+            if self.task_graph.add_edge(from, to, 0).is_err() {
+                return Err(HastyError::TaskCycle(find_cycle(
+                    &self.task_graph,
+                    from,
+                    to,
+                )));
+            }
+        }
+
+        Ok(())
     }
 
     pub fn add_script(&mut self, script: &Script) {
         self.scripts.insert(script.id(), script.clone());
 
         // add a node to the task graph if it's not a "__ROOT__" script
-        if script.id().starts_with("__ROOT__") == false {
-            self.task_graph.add_node(script.id());
+        let id = script.id();
+        if !id.starts_with("__ROOT__") && !self.task_nodes.contains_key(&id) {
+            let node = self.task_graph.add_node(id.clone());
+            self.task_nodes.insert(id, node);
+        }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn shutdown(&self) -> &Shutdown {
+        &self.options.shutdown
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// The pipeline entry for `task` in `package`, with the package's own hasty.json merged over
+    /// the root's entry
+    pub fn task_config(&self, package: &str, task: &str) -> Option<CommandConfig> {
+        let base = self.config.task_config(package, task);
+
+        match self
+            .package_configs
+            .get(package)
+            .and_then(|c| c.pipeline.get(task))
+        {
+            Some(task_override) => Some(task_override.apply(base, task)),
+            None => base.cloned(),
         }
     }
 
+    /// Whether `task` is defined by the root pipeline or by any package's hasty.json
+    pub fn defines_task(&self, task: &str) -> bool {
+        self.config.defines_task(task)
+            || self
+                .package_configs
+                .values()
+                .any(|c| c.pipeline.contains_key(task))
+    }
+
+    pub fn options(&self) -> &EngineOptions {
+        &self.options
+    }
+
+    pub fn package_manager(&self) -> PackageManager {
+        self.package_manager
+    }
+
+    pub fn task_graph(&self) -> &Dag<String, u32, u32> {
+        &self.task_graph
+    }
+
+    pub fn package_graph(&self) -> &Dag<String, u32, u32> {
+        &self.package_graph
+    }
+
+    /// The workspace package with the given name
+    pub fn workspace(&self, name: &str) -> Option<&PackageJSON> {
+        self.workspace_indexes
+            .get(name)
+            .map(|&i| &self.workspaces[i])
+    }
+
     pub fn scripts(&self) -> &HashMap<std::string::String, Script> {
         &self.scripts
     }
@@ -213,254 +661,1124 @@ impl Engine {
         &mut self.scripts
     }
 
-    pub async fn execute(&mut self, dry_run: bool) {
-        let now = SystemTime::now();
+    pub async fn execute(&mut self, dry_run: bool) -> Result<ExecutionSummary> {
+        let now = Instant::now();
 
-        // Walk the graph in topological order, executing each script
-        let mut topo = Topo::new(&self.task_graph.graph());
+        let summary = self.run_tasks(None, dry_run, RunControl::default())?.await;
 
-        let mut task_statuses = HashMap::<String, Receiver<ScriptStatus>>::new();
-        let mut tasks = vec![];
+        info!("finished in: {}", now.elapsed().as_secs());
 
-        while let Some(next_id) = topo.next(&self.task_graph.graph()) {
-            let script_id = &self.task_graph[next_id];
-            let mut script = self.scripts.get_mut(script_id).unwrap().clone();
-
-            let (script_watcher, script_recv) = watch::channel(ScriptStatus::Waiting);
+        Ok(summary)
+    }
 
-            task_statuses.insert(script_id.clone(), script_recv);
+    /// Prepares a run of `tasks`, or of every task when `None`. Tasks outside of the set are
+    /// treated as already finished. The returned future doesn't borrow the engine, so it can be
+    /// spawned and aborted, which stops the scripts it started. `control` stops single tasks of
+    /// the run and tells which of them are done.
+    pub fn run_tasks(
+        &self,
+        tasks: Option<&HashSet<String>>,
+        dry_run: bool,
+        control: RunControl,
+    ) -> Result<impl Future<Output = ExecutionSummary> + Send + 'static> {
+        let executor = ScriptExecutor {
+            scripts: self.scripts.clone(),
+            hashes: self.hash_scripts()?,
+            cache: self.cache.clone(),
+            runner: ScriptRunner {
+                mode: self.options.execution_mode,
+                package_manager: self.package_manager,
+                shutdown: self.options.shutdown.clone(),
+                grace_period: self.options.grace_period,
+                default_timeout: self.options.default_timeout,
+                timed_out_output_lines: self.options.timed_out_output_lines,
+                root_dir: self.dir.clone(),
+                packages: Arc::new(
+                    self.workspaces
+                        .iter()
+                        .map(|ws| (ws.name.clone(), ws.clone()))
+                        .collect(),
+                ),
+            },
+            dry_run,
+            control,
+        };
+        let scheduler = self.scheduler(tasks);
+
+        Ok(async move { scheduler.run(&executor).await })
+    }
 
-            let mut deps_channels = vec![];
+    /// The given tasks along with every task downstream of them in the task graph
+    pub fn downstream_tasks<'a>(
+        &self,
+        tasks: impl IntoIterator<Item = &'a str>,
+    ) -> HashSet<String> {
+        let graph = self.task_graph.graph();
+        let mut result = HashSet::new();
+
+        for task in tasks {
+            let node = match self.task_nodes.get(task) {
+                Some(x) => *x,
+                None => continue,
+            };
 
-            // subscribe to a task's dependencies status channels
-            if script.has_dependencies() {
-                for (from, to) in &self.deps {
-                    if String::from(to) == script.id() {
-                        deps_channels.push(task_statuses.get(from).unwrap().clone());
-                    }
-                }
+            let mut dfs = Dfs::new(graph, node);
+            while let Some(next) = dfs.next(graph) {
+                result.insert(self.task_graph[next].clone());
             }
+        }
 
-            // add a task that we can await later to ensure things get cleaned up correctly
-            tasks.push(tokio::spawn(async move {
-                let mut deps_remaining = deps_channels.len();
+        result
+    }
 
-                // TODO: there's probably a better way to accomplish waiting for deps
-                while deps_remaining > 0 {
-                    for ch in deps_channels.iter_mut() {
-                        // If the channel has a value of ScriptStatus::Finished
-                        if *ch.borrow() == ScriptStatus::Finished && deps_remaining > 0 {
-                            deps_remaining -= 1;
-                        }
-                        ch.changed().await;
-                    }
+    /// Describes every task a run would execute, sorted by task id so plans can be diffed
+    pub fn plan(&self) -> Result<Vec<TaskPlan>> {
+        let hashes = self.hash_scripts()?;
+        let root_dir = filter::without_cur_dir(&self.dir);
+
+        let mut plans = vec![];
+
+        for node in self.task_graph.graph().node_indices() {
+            let task_id = &self.task_graph[node];
+            let script = self
+                .scripts
+                .get(task_id)
+                .ok_or_else(|| HastyError::UnknownTask {
+                    task: task_id.clone(),
+                    dependent: None,
+                })?;
+
+            let body = self
+                .workspace(&script.package_name)
+                .and_then(|ws| ws.scripts.as_ref())
+                .and_then(|scripts| scripts.get(&script.command))
+                .cloned();
+
+            let command = match self.options.execution_mode {
+                ExecutionMode::PackageManager => {
+                    let mut command = vec![self.package_manager.binary().to_string()];
+                    command.extend(self.package_manager.run_args(&script.command));
+                    command
                 }
+                ExecutionMode::Shell => vec![
+                    String::from("sh"),
+                    String::from("-c"),
+                    body.clone().unwrap_or_default(),
+                ],
+            };
 
-                if dry_run {
-                    info!("execute: {}", script.id());
-                } else {
-                    let mut child = script.execute();
+            let dir = filter::without_cur_dir(&script.dir);
+            let dir = match dir.strip_prefix(&root_dir) {
+                Ok(relative) if relative.as_os_str().is_empty() => PathBuf::from("."),
+                Ok(relative) => relative.to_path_buf(),
+                Err(_) => dir,
+            };
 
-                    let status = match child.wait().await {
-                        Ok(status) => Some(status),
-                        Err(err) => {
-                            error!("Error running script: {:?}", err);
-                            None
-                        }
-                    };
-                }
+            let mut dependencies = self
+                .task_graph
+                .parents(node)
+                .iter(&self.task_graph)
+                .map(|(_, parent)| self.task_graph[parent].clone())
+                .collect::<Vec<String>>();
+            dependencies.sort();
+            dependencies.dedup();
+
+            let mut dependents = self
+                .task_graph
+                .children(node)
+                .iter(&self.task_graph)
+                .map(|(_, child)| self.task_graph[child].clone())
+                .collect::<Vec<String>>();
+            dependents.sort();
+            dependents.dedup();
+
+            plans.push(TaskPlan {
+                task: task_id.clone(),
+                package: script.package_name.clone(),
+                dir,
+                command,
+                script: body,
+                dependencies,
+                dependents,
+                files: script.config.files.clone(),
+                output: script.config.output.clone(),
+                persistent: script.config.persistent,
+                readiness: script.config.readiness.clone(),
+                timeout: script.config.timeout,
+                hash: hashes.get(task_id).cloned().unwrap_or_default(),
+            });
+        }
+
+        plans.sort_by(|a, b| a.task.cmp(&b.task));
+
+        Ok(plans)
+    }
+
+    // Builds a scheduler over the task graph, or the part of it made of `only`. Tasks are listed
+    // in topological order so tasks that become ready at the same time are always dispatched in
+    // the same order
+    fn scheduler(&self, only: Option<&HashSet<String>>) -> Scheduler {
+        let graph = self.task_graph.graph();
+        let included = |id: &String| only.is_none_or(|only| only.contains(id));
 
-                script_watcher.send_replace(ScriptStatus::Finished);
-            }));
+        let mut tasks = vec![];
+        let mut topo = Topo::new(graph);
+        while let Some(next_id) = topo.next(graph) {
+            if included(&self.task_graph[next_id]) {
+                tasks.push(self.task_graph[next_id].clone());
+            }
         }
 
-        join_all(tasks).await;
+        let edges = graph
+            .raw_edges()
+            .iter()
+            .map(|e| {
+                (
+                    self.task_graph[e.source()].clone(),
+                    self.task_graph[e.target()].clone(),
+                )
+            })
+            .filter(|(from, to)| included(from) && included(to))
+            .collect::<Vec<(String, String)>>();
+
+        let persistent = tasks
+            .iter()
+            .filter(|id| self.scripts.get(*id).is_some_and(|s| s.config.persistent))
+            .cloned()
+            .collect();
+
+        Scheduler::new(
+            tasks,
+            &edges,
+            self.options.concurrency,
+            self.options.continue_on_error,
+        )
+        .with_persistent(persistent)
+        .with_shutdown(self.options.shutdown.clone())
+    }
+
+    // Computes each task's hash in topological order, so the hashes of a task's upstream tasks
+    // are always available when it is hashed
+    fn hash_scripts(&self) -> Result<HashMap<String, String>> {
+        let mut hashes = HashMap::<String, String>::new();
+
+        let mut upstream = HashMap::<&str, Vec<&str>>::new();
+        for (from, to) in &self.deps {
+            upstream.entry(to).or_default().push(from);
+        }
 
-        info!("finished in: {}", now.elapsed().unwrap().as_secs());
+        let mut topo = Topo::new(self.task_graph.graph());
 
-        if dry_run {
-            info!(
-                "visualized task graph: {}",
-                generate_graphviz_url_from_graph(&self.task_graph)
+        while let Some(next_id) = topo.next(self.task_graph.graph()) {
+            let script_id = &self.task_graph[next_id];
+            let script = self
+                .scripts
+                .get(script_id)
+                .ok_or_else(|| HastyError::UnknownTask {
+                    task: script_id.clone(),
+                    dependent: None,
+                })?;
+            let package = self.workspace(&script.package_name);
+
+            let upstream_hashes = upstream
+                .get(script_id.as_str())
+                .into_iter()
+                .flatten()
+                .filter_map(|from| hashes.get(*from).cloned())
+                .collect::<Vec<String>>();
+
+            let hash = self.cache.hash_script(
+                script,
+                self.options.execution_mode,
+                self.package_manager,
+                package,
+                self.external_dependencies(&script.package_name),
+                &upstream_hashes,
             );
+            hashes.insert(script.id(), hash);
         }
+
+        Ok(hashes)
     }
 
-    pub fn resolve_workspace_scripts(&mut self) {
+    /// Creates each pipeline task's script in every package that defines it, using the
+    /// package's `package#task` pipeline entry over the generic one when it has one. Same-package
+    /// and `package#task` dependencies are resolved too, adding the scripts they point to even
+    /// when their package isn't selected.
+    pub fn resolve_workspace_scripts(&mut self) -> Result<()> {
         let cur_scripts = self
             .scripts()
             .values()
-            .map(|s| (s.id(), s.command.clone()))
-            .collect::<Vec<(String, String)>>();
+            .map(|s| s.command.clone())
+            .collect::<Vec<String>>();
 
         let mut scripts_to_add = vec![];
 
         for ws in self.workspaces.iter() {
+            if let Some(selected) = &self.selected_packages {
+                if !selected.contains(&ws.name) {
+                    continue;
+                }
+            }
+
             let ws_scripts = match &ws.scripts {
                 Some(x) => x,
                 None => continue,
             };
 
             // ignore packages that don't include the main script we are running
-            if self
+            if !self
                 .called_scripts
                 .iter()
                 .any(|s| ws_scripts.contains_key(s))
-                == false
             {
                 continue;
             }
 
-            for (script_id, script_name) in &cur_scripts {
-                if ws_scripts.contains_key(script_name) {
-                    let mut ws_script = self.scripts.get(script_id).unwrap().clone();
-
-                    ws_script.package_name = ws.name.clone();
+            for script_name in &cur_scripts {
+                if !ws_scripts.contains_key(script_name) {
+                    continue;
+                }
 
-                    if let Some(ws_dir) = ws.dir.clone() {
-                        ws_script.dir = ws_dir;
-                    }
+                // tasks that only have entries scoped to other packages don't run here
+                let config = match self.task_config(&ws.name, script_name) {
+                    Some(x) => x,
+                    None => continue,
+                };
 
-                    // ensure package-level deps are represented
-                    if let Some(script_deps) = &ws_script.dependencies() {
-                        for d in script_deps {
-                            if ws_scripts.contains_key(d) {
-                                self.deps
-                                    .push((make_script_id(&ws.name, d), ws_script.id()));
-                            }
-                        }
-                    }
+                let dir = ws.dir.as_deref().unwrap_or(&self.dir);
 
-                    // collect the script so we can add it to our engine after we're done iterating throught the workspaces
-                    scripts_to_add.push(ws_script);
-                }
+                // collect the script so we can add it to our engine after we're done iterating throught the workspaces
+                scripts_to_add.push(Script::new(config, dir, &ws.name));
             }
         }
 
         for script in scripts_to_add.iter() {
             self.add_script(script);
         }
+
+        let mut pending = scripts_to_add;
+
+        while let Some(script) = pending.pop() {
+            let same_package = script
+                .dependencies()
+                .unwrap_or_default()
+                .into_iter()
+                .map(|d| (script.package_name.clone(), d, false));
+            let explicit = script
+                .package_dependencies()
+                .unwrap_or_default()
+                .into_iter()
+                .map(|(package, d)| (package, d, true));
+
+            for (package_name, task, is_explicit) in same_package.chain(explicit) {
+                let dep_id = make_script_id(&package_name, &task);
+
+                let ws = self
+                    .workspace(&package_name)
+                    .filter(|ws| ws.scripts.as_ref().is_some_and(|s| s.contains_key(&task)));
+
+                // a missing same-package script is fine, since not every package has every task
+                let dir = match ws {
+                    Some(ws) => ws.dir.clone().unwrap_or_else(|| self.dir.clone()),
+                    None if is_explicit => {
+                        return Err(HastyError::MissingScript {
+                            task: dep_id,
+                            dependent: script.id(),
+                        })
+                    }
+                    None => continue,
+                };
+
+                self.deps.push((dep_id.clone(), script.id()));
+
+                if self.scripts.contains_key(&dep_id) {
+                    continue;
+                }
+
+                let config = self.task_config(&package_name, &task).ok_or_else(|| {
+                    HastyError::UnknownTask {
+                        task: dep_id.clone(),
+                        dependent: Some(script.id()),
+                    }
+                })?;
+
+                let dep_script = Script::new(config, &dir, &package_name);
+
+                self.add_script(&dep_script);
+                pending.push(dep_script);
+            }
+        }
+
+        Ok(())
     }
 
-    pub fn build_package_graph(&mut self) {
+    /// Adds a node for every workspace package and an edge for each dependency between them,
+    /// from any of the dependency fields in package.json. Dependencies on packages outside of the
+    /// workspace, or whose version spec the workspace package doesn't satisfy, are recorded in
+    /// `external_dependencies` instead.
+    pub fn build_package_graph(&mut self) -> Result<()> {
         for ws in &self.workspaces {
-            let pkg_node_id = self.package_graph.add_node(String::from(&ws.name));
+            if !self.package_nodes.contains_key(&ws.name) {
+                let node = self.package_graph.add_node(ws.name.clone());
+                self.package_nodes.insert(ws.name.clone(), node);
+            }
+        }
 
-            if let Some(ws_deps) = &ws.dependencies {
-                for dep in ws_deps.keys() {
-                    let dep_node_id = find_node_index(&self.package_graph, String::from(dep));
+        let mut edges = vec![];
 
-                    if None == dep_node_id {
-                        self.package_graph
-                            .add_parent(pkg_node_id, 1, String::from(dep));
-                    } else if let Some(dep_node_id) = dep_node_id {
-                        self.package_graph.add_edge(dep_node_id, pkg_node_id, 1);
+        for ws in &self.workspaces {
+            let pkg_node_id = self.package_nodes[&ws.name];
+
+            for (dep, version) in ws.all_dependencies() {
+                let local_version = self
+                    .workspace_indexes
+                    .get(dep)
+                    .map(|&i| self.workspaces[i].version.as_deref());
+
+                let dep_node_id = match (self.package_nodes.get(dep), local_version) {
+                    (Some(x), Some(local_version))
+                        if version::links_to_workspace(version, local_version) =>
+                    {
+                        Some(*x)
                     }
-                }
+                    (Some(_), Some(local_version)) => {
+                        // an alias names a different package, so there's nothing to warn about
+                        if !version::is_alias(version) {
+                            warn!(
+                                "{} depends on {}@{}, which the workspace version {} doesn't satisfy, treating it as external",
+                                ws.name,
+                                dep,
+                                version,
+                                local_version.unwrap_or_default()
+                            );
+                        }
+
+                        None
+                    }
+                    _ => None,
+                };
+
+                let dep_node_id = match dep_node_id {
+                    Some(x) => x,
+                    None => {
+                        self.external_dependencies
+                            .entry(ws.name.clone())
+                            .or_default()
+                            .entry(dep.clone())
+                            .or_insert_with(|| version.clone());
+                        continue;
+                    }
+                };
+
+                edges.push((dep_node_id, pkg_node_id));
             }
+        }
 
-            if let Some(ws_dev_deps) = &ws.dev_dependencies {
-                for dep in ws_dev_deps.keys() {
-                    let dep_node_id = find_node_index(&self.package_graph, String::from(dep));
+        if add_edges_checked_once(&mut self.package_graph, &edges, 1) {
+            return Ok(());
+        }
 
-                    if None == dep_node_id {
-                        self.package_graph
-                            .add_parent(pkg_node_id, 1, String::from(dep));
-                    } else if let Some(dep_node_id) = dep_node_id {
-                        self.package_graph.add_edge(dep_node_id, pkg_node_id, 1);
-                    }
+        for (dep_node_id, pkg_node_id) in edges {
+            if self
+                .package_graph
+                .add_edge(dep_node_id, pkg_node_id, 1)
+                .is_err()
+            {
+                let cycle = find_cycle(&self.package_graph, dep_node_id, pkg_node_id);
+
+                match self.config.package_cycles {
+                    CycleBehavior::Error => return Err(HastyError::PackageCycle(cycle)),
+                    CycleBehavior::Warn => warn!(
+                        "cycle detected in the package graph, ignoring the dependency of {} on {}: {}",
+                        self.package_graph[pkg_node_id],
+                        self.package_graph[dep_node_id],
+                        cycle.join(" -> ")
+                    ),
                 }
             }
         }
+
+        Ok(())
+    }
+
+    /// The dependencies of `package` that aren't workspace packages, with their version ranges.
+    /// Only available after `build_package_graph`.
+    pub fn external_dependencies(&self, package: &str) -> Option<&BTreeMap<String, String>> {
+        self.external_dependencies.get(package)
+    }
+
+    /// Restricts the packages scripts are resolved in to the ones matched by `selectors`. Relies
+    /// on the package graph, so it must be called after `build_package_graph` and before
+    /// `resolve_workspace_scripts`.
+    pub fn filter_packages(&mut self, selectors: &[Selector]) {
+        if selectors.is_empty() {
+            return;
+        }
+
+        self.selected_packages = Some(filter::select_packages(
+            selectors,
+            &self.workspaces,
+            &self.package_graph,
+            &self.package_nodes,
+            &self.dir,
+        ));
+    }
+
+    /// Restricts the packages scripts are resolved in to the ones affected by changes since the
+    /// git ref `since`, along with their dependents. Combines with any selection made by
+    /// `filter_packages`, and has the same ordering requirements.
+    pub fn filter_changed_since(&mut self, since: &str) -> Result<()> {
+        let changed_files = git::changed_files(&self.dir, since).map_err(HastyError::Git)?;
+        let affected = git::affected_packages(
+            &changed_files,
+            &self.workspaces,
+            &self.package_graph,
+            &self.package_nodes,
+            &self.dir,
+        );
+
+        self.selected_packages = Some(match self.selected_packages.take() {
+            Some(selected) => selected.intersection(&affected).cloned().collect(),
+            None => affected,
+        });
+
+        Ok(())
+    }
+
+    pub fn selected_packages(&self) -> Option<&HashSet<String>> {
+        self.selected_packages.as_ref()
     }
 
     pub fn add_topo_task_deps(&mut self) {
-        let cur_scripts = self
-            .scripts()
-            .values()
-            .map(|s| s.clone())
-            .collect::<Vec<Script>>();
+        let cur_scripts = self.scripts().values().cloned().collect::<Vec<Script>>();
 
         for s in &cur_scripts {
             let package_name = &s.package_name;
 
-            if s.has_dependencies() == false {
+            if !s.has_dependencies() {
                 continue;
             }
 
             // check the script's dependencies for any topological dependencies. Uses the package_graph to determine topological task dependencies.
-            for d in s.topological_dependencies().unwrap() {
-                let package_node_index =
-                    find_node_index(&self.package_graph, String::from(package_name)).unwrap();
+            for d in s.topological_dependencies().unwrap_or_default() {
+                let package_node_index = match self.package_nodes.get(package_name) {
+                    Some(x) => *x,
+                    None => continue,
+                };
                 let mut package_parents = self.package_graph.parents(package_node_index);
 
-                for (_, parent_package_index) in package_parents.walk_next(&self.package_graph) {
+                while let Some((_, parent_package_index)) =
+                    package_parents.walk_next(&self.package_graph)
+                {
                     let parent_package_name = self
                         .package_graph
                         .node_weight(parent_package_index)
                         .unwrap();
-                    let parent_package = self
-                        .workspaces
-                        .iter()
-                        .find(|ws| ws.name == String::from(parent_package_name))
-                        .unwrap();
+                    let has_script = self
+                        .workspace_indexes
+                        .get(parent_package_name)
+                        .and_then(|&i| self.workspaces[i].scripts.as_ref())
+                        .is_some_and(|scripts| scripts.contains_key(&d));
+
+                    if has_script {
+                        // The parent script contains the topological dependency, so add a dep to the task graph
+                        self.deps
+                            .push((make_script_id(parent_package_name, &d), s.id()));
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Executes the engine's scripts on behalf of the scheduler
+struct ScriptExecutor {
+    scripts: HashMap<String, Script>,
+    hashes: HashMap<String, String>,
+    cache: Cache,
+    runner: ScriptRunner,
+    dry_run: bool,
+    control: RunControl,
+}
+
+impl TaskExecutor for ScriptExecutor {
+    fn execute(
+        &self,
+        task_id: &str,
+        ready: oneshot::Sender<()>,
+    ) -> impl Future<Output = ScriptStatus> {
+        let script = self.scripts.get(task_id).cloned();
+        let hash = self.hashes.get(task_id).cloned().unwrap_or_default();
+        let cache = self.cache.clone();
+        let runner = self.runner.clone();
+        let dry_run = self.dry_run;
+        let control = self.control.clone();
+
+        let task_id = task_id.to_string();
+
+        async move {
+            if control.is_cancelled(&task_id) {
+                return ScriptStatus::Cancelled;
+            }
+
+            let mut script = match script {
+                Some(script) => script,
+                None => {
+                    error!(
+                        "{}",
+                        HastyError::UnknownTask {
+                            task: task_id,
+                            dependent: None
+                        }
+                    );
+                    return ScriptStatus::Error;
+                }
+            };
+
+            if dry_run {
+                info!("execute: {} ({})", script.id(), hash);
+                control.settle(&task_id);
+                return ScriptStatus::Finished;
+            }
+
+            // a task counts as done for the run's control once it is ready
+            let (tx, rx) = oneshot::channel();
+            let forward_ready = async {
+                if rx.await.is_ok() {
+                    control.settle(&task_id);
+                    let _ = ready.send(());
+                }
+            };
+
+            // run on its own tokio task so cache I/O doesn't hold up the scheduler
+            let id = script.id();
+            let run = async move { run_script(&mut script, &hash, &cache, &runner, tx).await };
+            let handle = AbortOnDrop(tokio::spawn(run));
 
-                    if let Some(parent_scripts) = &parent_package.scripts {
-                        if parent_scripts.get(&d) != None {
-                            // The parent script contains the topological dependency, so add a dep to the task graph
-                            self.deps
-                                .push((make_script_id(parent_package_name, &d), s.id()));
+            // owns the handle, so a cancelled script is stopped as soon as the select returns
+            let finished = async {
+                let mut handle = handle;
+
+                tokio::select! {
+                    result = &mut handle.0 => match result {
+                        Ok(status) => status,
+                        Err(err) => {
+                            error!("Task {} panicked: {:?}", id, err);
+                            ScriptStatus::Error
+                        }
+                    },
+                    () = control.cancelled(&task_id) => ScriptStatus::Cancelled,
+                }
+            };
+
+            let (status, ()) = futures::join!(finished, forward_ready);
+            control.settle(&task_id);
+
+            status
+        }
+    }
+}
+
+/// Stops single tasks of a run and tracks which of its tasks are done, so watch mode can restart
+/// some tasks without touching the others. Clones share the same state.
+#[derive(Debug, Clone)]
+pub struct RunControl {
+    cancelled: Arc<tokio::sync::watch::Sender<HashSet<String>>>,
+    settled: Arc<Mutex<HashSet<String>>>,
+}
+
+impl Default for RunControl {
+    fn default() -> Self {
+        RunControl {
+            cancelled: Arc::new(tokio::sync::watch::channel(HashSet::new()).0),
+            settled: Arc::default(),
+        }
+    }
+}
+
+impl RunControl {
+    /// Stops the given tasks if they are running, and keeps them from starting otherwise. They
+    /// are reported as cancelled, and their dependents in the run are skipped.
+    pub fn cancel(&self, tasks: &HashSet<String>) {
+        self.cancelled
+            .send_modify(|cancelled| cancelled.extend(tasks.iter().cloned()));
+    }
+
+    /// Whether the task finished, was cancelled, or is ready for its dependents
+    pub fn is_settled(&self, task: &str) -> bool {
+        self.settled.lock().unwrap().contains(task)
+    }
+
+    fn settle(&self, task: &str) {
+        self.settled.lock().unwrap().insert(task.to_string());
+    }
+
+    fn is_cancelled(&self, task: &str) -> bool {
+        self.cancelled.borrow().contains(task)
+    }
+
+    // Resolves once the task is cancelled
+    async fn cancelled(&self, task: &str) {
+        let mut rx = self.cancelled.subscribe();
+
+        while !rx.borrow_and_update().contains(task) {
+            if rx.changed().await.is_err() {
+                return std::future::pending().await;
+            }
+        }
+    }
+}
+
+// Aborts a spawned task when dropped, so cancelling a run also stops the scripts it started
+struct AbortOnDrop<T>(JoinHandle<T>);
+
+impl<T> Drop for AbortOnDrop<T> {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+// Everything needed to start a script's process in the configured execution mode
+#[derive(Clone)]
+struct ScriptRunner {
+    mode: ExecutionMode,
+    package_manager: PackageManager,
+    shutdown: Shutdown,
+    grace_period: Duration,
+    default_timeout: Option<Duration>,
+    timed_out_output_lines: usize,
+    root_dir: PathBuf,
+    packages: Arc<HashMap<String, PackageJSON>>,
+}
+
+impl ScriptRunner {
+    async fn run(&self, script: &mut Script) -> (Result<ExitStatus>, Vec<String>) {
+        let deadline = self
+            .timeout(script)
+            .map(|timeout| tokio::time::Instant::now() + timeout);
+
+        match self.mode {
+            ExecutionMode::PackageManager => match script.execute(self.package_manager) {
+                Ok(process) => self.wait(script, process, deadline).await,
+                Err(err) => (Err(err), vec![]),
+            },
+            ExecutionMode::Shell => self.run_in_shell(script, deadline).await,
+        }
+    }
+
+    // The script's own timeout, or the default one unless the script is persistent
+    fn timeout(&self, script: &Script) -> Option<Duration> {
+        match script.config().timeout {
+            Some(seconds) => Some(Duration::from_secs(seconds)),
+            None if script.config().persistent => None,
+            None => self.default_timeout,
+        }
+    }
+
+    // Runs each lifecycle stage of the script in turn, stopping at the first one that fails. The
+    // deadline applies to all of the stages together.
+    async fn run_in_shell(
+        &self,
+        script: &mut Script,
+        deadline: Option<tokio::time::Instant>,
+    ) -> (Result<ExitStatus>, Vec<String>) {
+        let stages = match self.packages.get(&script.package_name) {
+            Some(package) => shell::lifecycle_stages(package, &script.command)
+                .into_iter()
+                .map(|(stage, body)| {
+                    let env = shell::lifecycle_env(&self.root_dir, package, &stage, &body);
+                    (body, env)
+                })
+                .collect::<Vec<_>>(),
+            None => vec![],
+        };
+
+        if stages.is_empty() {
+            let err = HastyError::Process {
+                task: script.id(),
+                source: io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("no script named {} in package.json", script.command),
+                ),
+            };
+            return (Err(err), vec![]);
+        }
+
+        let mut output = vec![];
+        let mut last_status = None;
+
+        for (body, env) in stages {
+            let (status, mut stage_output) = match script.execute_in_shell(&body, env) {
+                Ok(process) => self.wait(script, process, deadline).await,
+                Err(err) => (Err(err), vec![]),
+            };
+            output.append(&mut stage_output);
+
+            match status {
+                Ok(status) if status.success() => last_status = Some(status),
+                _ => return (status, output),
+            }
+        }
+
+        // there is at least one stage, and every stage succeeded
+        (Ok(last_status.unwrap()), output)
+    }
+
+    async fn wait(
+        &self,
+        script: &Script,
+        process: ScriptProcess,
+        deadline: Option<tokio::time::Instant>,
+    ) -> (Result<ExitStatus>, Vec<String>) {
+        let (status, output, stop) = process
+            .wait(&self.shutdown, deadline, self.grace_period)
+            .await;
+
+        let err = match stop {
+            Some(Stop::Signal(signal)) => HastyError::Interrupted {
+                task: script.id(),
+                signal,
+            },
+            Some(Stop::TimedOut) => HastyError::TimedOut {
+                task: script.id(),
+                timeout: self.timeout(script).unwrap_or_default(),
+            },
+            None => {
+                let status = status.map_err(|source| HastyError::Process {
+                    task: script.id(),
+                    source,
+                });
+
+                return (status, output);
+            }
+        };
+
+        (Err(err), output)
+    }
+}
+
+// Runs a single script, or restores it from the cache when its hash has been seen before. `ready`
+// is sent on when the script's readiness probe passes while it is still running.
+async fn run_script(
+    script: &mut Script,
+    hash: &str,
+    cache: &Cache,
+    runner: &ScriptRunner,
+    ready: oneshot::Sender<()>,
+) -> ScriptStatus {
+    // restoring a persistent task would skip starting it, so they always run
+    let persistent = script.config().persistent;
+    let restored = if persistent {
+        Ok(None)
+    } else {
+        cache.restore(hash, script)
+    };
+
+    match restored {
+        Ok(Some(logs)) => {
+            info!(target: &script.id(), "cache hit, replaying logs {}", hash);
+
+            for line in logs {
+                info!(target: &script.id(), "{}", line);
+            }
+
+            ScriptStatus::Finished
+        }
+        Ok(None) => {
+            let (status, output) = match script.config().readiness.clone() {
+                Some(readiness) => {
+                    let (tx, rx) = mpsc::unbounded_channel();
+                    script.forward_output(tx);
+
+                    let id = script.id();
+                    let probe = readiness.wait(script.dir.clone(), rx);
+                    let run = runner.run(script);
+                    tokio::pin!(run);
+
+                    tokio::select! {
+                        result = &mut run => result,
+                        () = probe => {
+                            info!(target: &id, "ready");
+                            let _ = ready.send(());
+                            run.await
                         }
                     }
                 }
+                None => runner.run(script).await,
+            };
+
+            match status {
+                Ok(status) if status.success() => {
+                    if !persistent {
+                        if let Err(err) = cache.save(hash, script, &output) {
+                            warn!("Error saving {} to the cache: {:?}", script.id(), err);
+                        }
+                    }
+
+                    ScriptStatus::Finished
+                }
+                Ok(status) => {
+                    error!("{} exited with {}", script.id(), status);
+                    ScriptStatus::Error
+                }
+                Err(err @ HastyError::Interrupted { .. }) => {
+                    warn!("{}", err);
+                    ScriptStatus::Interrupted
+                }
+                Err(err @ HastyError::TimedOut { .. }) => {
+                    error!("{}", err);
+
+                    // the output was logged as it arrived, but may be buried under other tasks'
+                    let lines = runner.timed_out_output_lines;
+                    let tail = &output[output.len().saturating_sub(lines)..];
+                    if !tail.is_empty() {
+                        error!("last {} lines of output from {}:", tail.len(), script.id());
+                    }
+                    for line in tail {
+                        error!(target: &script.id(), "{}", line);
+                    }
+
+                    ScriptStatus::TimedOut
+                }
+                Err(err) => {
+                    error!("{}", err);
+                    ScriptStatus::Error
+                }
             }
         }
+        Err(err) => {
+            error!("Error restoring {} from the cache: {:?}", script.id(), err);
+            ScriptStatus::Error
+        }
     }
 }
 
-fn find_node_index<NodeType: std::cmp::PartialEq>(
-    graph: &Dag<NodeType, u32, u32>,
-    node: NodeType,
-) -> Option<NodeIndex> {
-    return graph.node_identifiers().find(|i| node == graph[*i]);
+// Adds all of the edges with a single cycle check, which is much faster than the check `add_edge`
+// does for every edge. Returns false without adding any edges when they would form a cycle, so
+// the caller can add them one at a time to find it.
+fn add_edges_checked_once(
+    graph: &mut Dag<String, u32, u32>,
+    edges: &[(NodeIndex, NodeIndex)],
+    weight: u32,
+) -> bool {
+    graph
+        .add_edges(edges.iter().map(|(from, to)| (*from, *to, weight)))
+        .is_ok()
 }
 
-fn generate_graphviz_url_from_graph<N: std::fmt::Debug, E: std::fmt::Debug>(
-    graph: &Dag<N, E>,
-) -> String {
-    let encoded_graph =
-        encode(&format!("{:?}", daggy::petgraph::dot::Dot::new(graph))).into_owned();
+// Describes the cycle that adding an edge from `from` to `to` would close, as the chain of node
+// names starting and ending at `from`. Such an edge is only rejected when `to` already reaches
+// `from`, so the rest of the chain is the shortest path between the two.
+fn find_cycle(graph: &Dag<String, u32, u32>, from: NodeIndex, to: NodeIndex) -> Vec<String> {
+    let mut previous = HashMap::<NodeIndex, NodeIndex>::new();
+    let mut queue = VecDeque::from([to]);
+
+    while let Some(node) = queue.pop_front() {
+        if node == from {
+            break;
+        }
+
+        for (_, next) in graph.children(node).iter(graph) {
+            if next != to && !previous.contains_key(&next) {
+                previous.insert(next, node);
+                queue.push_back(next);
+            }
+        }
+    }
+
+    let mut path = vec![from];
+    let mut node = from;
+    while node != to {
+        node = match previous.get(&node) {
+            Some(x) => *x,
+            None => break,
+        };
+        path.push(node);
+    }
+    path.push(from);
+    path.reverse();
 
-    format!(
-        "https://dreampuf.github.io/GraphvizOnline#{}",
-        encoded_graph
-    )
+    path.into_iter().map(|i| graph[i].clone()).collect()
 }
 
-// Spawns a task to handle reading a child process's stdio and logging it with the log crate
-fn pipe_child_stdio(stdio: impl AsyncRead + Unpin + Send + 'static, prefix: String) {
+// Spawns a task to handle reading a child process's stdio and logging it with the log crate. Each
+// line is also captured in `output` so it can be stored alongside the task's cached outputs, and
+// sent to `lines` when a readiness probe is watching the output. Bytes that aren't valid UTF-8
+// are replaced rather than ending the output early.
+fn pipe_child_stdio(
+    stdio: impl AsyncRead + Unpin + Send + 'static,
+    prefix: String,
+    output: Arc<Mutex<Vec<String>>>,
+    lines: Option<mpsc::UnboundedSender<String>>,
+) -> JoinHandle<()> {
     tokio::spawn(async move {
-        let mut reader = BufReader::new(stdio).lines();
+        let mut reader = BufReader::new(stdio);
+        let mut buf = vec![];
+
+        while let Ok(read) = reader.read_until(b'\n', &mut buf).await {
+            if read == 0 {
+                break;
+            }
+
+            let raw = buf.strip_suffix(b"\n").unwrap_or(&buf);
+            let raw = raw.strip_suffix(b"\r").unwrap_or(raw);
+            let line = String::from_utf8_lossy(raw).into_owned();
+            buf.clear();
 
-        while let Some(line) = reader.next_line().await.unwrap() {
             info!(target: &prefix, "{}", &line);
+            if let Some(lines) = &lines {
+                let _ = lines.send(line.clone());
+            }
+            output.lock().unwrap().push(line);
         }
-    });
+    })
+}
+
+pub fn load_config_file(opts: &options::HastyOptions) -> Result<Config> {
+    let dir = match &opts.dir {
+        Some(opts_dir) => opts_dir.to_path_buf(),
+        None => env::current_dir().map_err(|source| HastyError::Io {
+            path: PathBuf::from("."),
+            source,
+        })?,
+    };
+
+    let path = dir.join(CONFIG_FILE_NAME);
+
+    let raw = fs::read_to_string(&path).map_err(|source| HastyError::Io {
+        path: path.clone(),
+        source,
+    })?;
+    let config: Config =
+        serde_json::from_str(&raw).map_err(|source| HastyError::Json { path, source })?;
+
+    Ok(config)
+}
+
+pub fn make_script_id(package_name: &str, script_name: &str) -> String {
+    format!("{}{}{}", package_name, PACKAGE_TASK_SEPARATOR, script_name)
+}
+
+/// Splits a `package#task` id into its package and task, or returns `None` for a bare task name
+pub fn split_script_id(id: &str) -> Option<(&str, &str)> {
+    id.split_once(PACKAGE_TASK_SEPARATOR)
 }
 
-pub fn load_config_file(opts: &options::HastyOptions) -> Config {
-    let mut dir = env::current_dir().unwrap();
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    // A graph holding `nodes`, with an edge for each `(from, to)` pair of indexes into them
+    fn dag(nodes: &[&str], edges: &[(usize, usize)]) -> (Dag<String, u32, u32>, Vec<NodeIndex>) {
+        let mut graph = Dag::new();
+        let indexes = nodes
+            .iter()
+            .map(|n| graph.add_node(String::from(*n)))
+            .collect::<Vec<NodeIndex>>();
+
+        for (from, to) in edges {
+            graph.add_edge(indexes[*from], indexes[*to], 1).unwrap();
+        }
 
-    if let Some(opts_dir) = &opts.dir {
-        dir = opts_dir.to_path_buf()
+        (graph, indexes)
     }
 
-    let raw = fs::read_to_string(dir.join(CONFIG_FILE_NAME)).unwrap();
-    let config: Config = serde_json::from_str(&raw).unwrap();
+    // A workspace where packages `a` and `b` depend on each other
+    fn create_cyclic_workspace(package_cycles: &str) -> TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        let files = [
+            (
+                "package.json",
+                String::from(r#"{ "name": "root", "workspaces": ["packages/*"] }"#),
+            ),
+            (
+                "hasty.json",
+                format!(
+                    r#"{{ "packageCycles": "{}", "pipeline": {{}} }}"#,
+                    package_cycles
+                ),
+            ),
+            (
+                "packages/a/package.json",
+                String::from(r#"{ "name": "a", "dependencies": { "b": "*" } }"#),
+            ),
+            (
+                "packages/b/package.json",
+                String::from(r#"{ "name": "b", "dependencies": { "a": "*" } }"#),
+            ),
+        ];
 
-    return config;
-}
+        for (path, contents) in files {
+            let path = dir.path().join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
 
-pub fn make_script_id(package_name: &str, script_name: &str) -> String {
-    format!("{}#{}", package_name, script_name)
+        dir
+    }
+
+    fn build_package_graph(dir: &Path) -> (Engine, Result<()>) {
+        let raw = fs::read_to_string(dir.join(CONFIG_FILE_NAME)).unwrap();
+        let config: Config = serde_json::from_str(&raw).unwrap();
+
+        let mut engine = Engine::new(config, dir, vec![], EngineOptions::default()).unwrap();
+        let result = engine.build_package_graph();
+
+        (engine, result)
+    }
+
+    #[test]
+    fn find_cycle_describes_a_self_loop() {
+        let (graph, nodes) = dag(&["a"], &[]);
+
+        assert_eq!(find_cycle(&graph, nodes[0], nodes[0]), ["a", "a"]);
+    }
+
+    #[test]
+    fn find_cycle_describes_a_two_node_cycle() {
+        let (graph, nodes) = dag(&["a", "b"], &[(0, 1)]);
+
+        assert_eq!(find_cycle(&graph, nodes[1], nodes[0]), ["b", "a", "b"]);
+    }
+
+    #[test]
+    fn find_cycle_follows_the_shortest_path_back() {
+        let (graph, nodes) = dag(&["a", "b", "c", "d"], &[(0, 1), (1, 2), (2, 3)]);
+        assert_eq!(
+            find_cycle(&graph, nodes[3], nodes[0]),
+            ["d", "a", "b", "c", "d"]
+        );
+
+        let (graph, nodes) = dag(&["a", "b", "c", "d"], &[(0, 1), (1, 2), (2, 3), (0, 3)]);
+        assert_eq!(find_cycle(&graph, nodes[3], nodes[0]), ["d", "a", "d"]);
+    }
+
+    #[test]
+    fn package_cycles_only_warn_by_default() {
+        let workspace = create_cyclic_workspace("warn");
+
+        let (engine, result) = build_package_graph(workspace.path());
+
+        assert!(result.is_ok());
+        // the dependency that closes the cycle is dropped
+        assert_eq!(engine.package_graph().edge_count(), 1);
+    }
+
+    #[test]
+    fn package_cycles_can_be_errors() {
+        let workspace = create_cyclic_workspace("error");
+
+        let (_, result) = build_package_graph(workspace.path());
+
+        match result {
+            Err(HastyError::PackageCycle(cycle)) => {
+                assert_eq!(cycle.len(), 3);
+                assert_eq!(cycle.first(), cycle.last());
+                assert!(cycle.contains(&String::from("a")) && cycle.contains(&String::from("b")));
+            }
+            other => panic!("expected a package cycle, got {:?}", other),
+        }
+    }
 }
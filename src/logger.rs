@@ -1,8 +1,12 @@
 use log::{Level, LevelFilter, Metadata, Record};
 
-static LOGGER: HastyLogger = HastyLogger;
+static LOGGER: HastyLogger = HastyLogger { stderr: false };
+static STDERR_LOGGER: HastyLogger = HastyLogger { stderr: true };
 
-pub struct HastyLogger;
+pub struct HastyLogger {
+    // whether to log to stderr, for when stdout holds output meant for other programs
+    stderr: bool,
+}
 
 impl log::Log for HastyLogger {
     fn enabled(&self, metadata: &Metadata) -> bool {
@@ -11,23 +15,35 @@ impl log::Log for HastyLogger {
 
     fn log(&self, record: &Record) {
         if self.enabled(record.metadata()) {
+            // messages from hasty's own modules, as opposed to a task's output, which is logged
+            // with the task id as its target
             let prefix = match record.target() {
-                "hasty" => String::from("[hasty] "),
+                target if target == "hasty" || target.starts_with("hasty::") => {
+                    String::from("[hasty] ")
+                }
                 _ => {
                     // ref: https://doc.rust-lang.org/std/fmt/#fillalignment
                     format!("{:<12} - ", record.target())
                 }
             };
 
-            println!("{}{}", prefix, record.args());
+            if self.stderr {
+                eprintln!("{}{}", prefix, record.args());
+            } else {
+                println!("{}{}", prefix, record.args());
+            }
         }
     }
 
     fn flush(&self) {}
 }
 
-pub fn init() {
-    log::set_logger(&LOGGER)
+/// Installs the logger, which writes to stdout unless `stderr` is set, as it should be when stdout
+/// is reserved for output meant for other programs, like `graph` and `--dry-run=json`
+pub fn init(stderr: bool) {
+    let logger = if stderr { &STDERR_LOGGER } else { &LOGGER };
+
+    log::set_logger(logger)
         .map(|()| log::set_max_level(LevelFilter::Info))
         .unwrap();
 }
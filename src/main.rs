@@ -1,43 +1,200 @@
 use clap::Parser;
-use hasty::{self, logger, make_script_id, Engine, Script};
+use hasty::{
+    self, graph,
+    graph::GraphKind,
+    logger, make_script_id,
+    options::{GraphOptions, HastyCommand, HastyOptions},
+    plan::DryRun,
+    shutdown::Shutdown,
+    CommandConfig, Engine, EngineOptions, ExecutionSummary, HastyError, Script,
+};
+use log::{error, info, warn};
+use std::{
+    fs,
+    io::{self, Write},
+    time::Duration,
+};
 
 #[tokio::main]
 async fn main() {
-    logger::init();
+    let options = HastyOptions::parse();
 
-    let options = hasty::options::HastyOptions::parse();
+    let machine_readable = matches!(options.command, Some(HastyCommand::Graph(_)))
+        || options.dry_run == Some(DryRun::Json);
+    logger::init(machine_readable);
+    // `hasty dev` without a script and without `--watch` means the `dev` task, as it did before
+    // `dev` became a subcommand
+    let dev_task = String::from("dev");
 
-    let working_dir = options
-        .dir
-        .clone()
-        .unwrap_or(std::env::current_dir().unwrap());
+    let script = match &options.command {
+        Some(HastyCommand::Graph(graph_options)) => {
+            exit_on_error(write_graph(&options, graph_options));
+            return;
+        }
+        Some(HastyCommand::Dev(dev_options)) if dev_options.watch => {
+            exit_on_error(watch(&options, dev_options.script.as_ref()).await);
+            return;
+        }
+        Some(HastyCommand::Dev(dev_options)) => dev_options.script.as_ref().or(Some(&dev_task)),
+        None => options.script.as_ref(),
+    };
 
-    let config = hasty::load_config_file(&options);
-    let mut tasks_to_execute = vec![];
+    if options.dry_run == Some(DryRun::Json) {
+        exit_on_error(write_plan(&options, script));
+        return;
+    }
 
-    match options.script {
-        Some(x) => {
-            if config.pipeline.contains_key(&x) == false {
-                panic!("Pipeline does not contain the provided script")
-            }
+    let shutdown = exit_on_error(Shutdown::listen());
+    let summary = exit_on_error(run(&options, script, shutdown.clone()).await);
 
-            tasks_to_execute.push(x)
-        }
+    if !summary.succeeded.is_empty() {
+        info!("succeeded tasks: {}", summary.succeeded.join(", "));
+    }
+
+    if !summary.skipped.is_empty() {
+        warn!("skipped because of failure: {}", summary.skipped.join(", "));
+    }
+
+    if !summary.interrupted.is_empty() {
+        warn!("interrupted tasks: {}", summary.interrupted.join(", "));
+    }
+
+    if !summary.not_started.is_empty() {
+        warn!("not started: {}", summary.not_started.join(", "));
+    }
+
+    if !summary.timed_out.is_empty() {
+        error!("timed out tasks: {}", summary.timed_out.join(", "));
+    }
+
+    if !summary.failed.is_empty() {
+        error!("failed tasks: {}", summary.failed.join(", "));
+    }
+
+    if let Some(signal) = shutdown.signal() {
+        std::process::exit(signal.exit_code());
+    }
+
+    if !summary.is_success() {
+        std::process::exit(1);
+    }
+}
+
+async fn run(
+    options: &HastyOptions,
+    script: Option<&String>,
+    shutdown: Shutdown,
+) -> Result<ExecutionSummary, HastyError> {
+    let mut engine = build_engine(options, script, shutdown)?;
+
+    engine.execute(options.dry_run.is_some()).await
+}
+
+// Runs every task once, then keeps rerunning the affected ones as files change until hasty is
+// interrupted
+async fn watch(options: &HastyOptions, script: Option<&String>) -> Result<(), HastyError> {
+    let shutdown = Shutdown::listen()?;
+    let engine = build_engine(options, script, shutdown.clone())?;
+
+    hasty::watch::watch(&engine, options.dry_run.is_some()).await?;
+
+    if let Some(signal) = shutdown.signal() {
+        std::process::exit(signal.exit_code());
+    }
+
+    Ok(())
+}
+
+fn write_plan(options: &HastyOptions, script: Option<&String>) -> Result<(), HastyError> {
+    let engine = build_engine(options, script, Shutdown::never())?;
+
+    // a plan is made of strings and paths, which always serialize
+    let plan = serde_json::to_string_pretty(&engine.plan()?).unwrap_or_default();
+
+    write_stdout(&format!("{}\n", plan))
+}
+
+fn write_graph(options: &HastyOptions, graph_options: &GraphOptions) -> Result<(), HastyError> {
+    let engine = build_engine(options, graph_options.script.as_ref(), Shutdown::never())?;
+
+    let graph = match graph_options.kind {
+        GraphKind::Task => engine.task_graph(),
+        GraphKind::Package => engine.package_graph(),
+    };
+
+    let rendered = graph::render(graph, graph_options.format);
+
+    match &graph_options.output {
+        Some(path) => fs::write(path, rendered).map_err(|source| HastyError::Io {
+            path: path.clone(),
+            source,
+        }),
+        None => write_stdout(&rendered),
+    }
+}
+
+// Writes output meant for other programs to stdout. A reader that stops early, like `head`,
+// isn't an error.
+fn write_stdout(contents: &str) -> Result<(), HastyError> {
+    let mut stdout = io::stdout().lock();
+
+    match write!(stdout, "{}", contents).and_then(|()| stdout.flush()) {
+        Err(err) if err.kind() != io::ErrorKind::BrokenPipe => Err(HastyError::Output(err)),
+        _ => Ok(()),
+    }
+}
+
+// Resolves the pipeline tasks for `script`, or every task when omitted, into the engine's
+// task graph. Runs stop when `shutdown` receives a signal.
+fn build_engine(
+    options: &HastyOptions,
+    script: Option<&String>,
+    shutdown: Shutdown,
+) -> Result<Engine, HastyError> {
+    let working_dir = match &options.dir {
+        Some(dir) => dir.clone(),
+        None => std::env::current_dir().map_err(|source| HastyError::Io {
+            path: ".".into(),
+            source,
+        })?,
+    };
+
+    let config = hasty::load_config_file(options)?;
+    let mut tasks_to_execute = vec![];
+
+    match script {
+        Some(x) => tasks_to_execute.push(x.clone()),
         None => {
-            for task in config.pipeline.keys() {
-                tasks_to_execute.push(String::from(task));
+            for task in config.task_names() {
+                if !tasks_to_execute.iter().any(|t| t == task) {
+                    tasks_to_execute.push(String::from(task));
+                }
             }
         }
     };
 
-    let mut engine = Engine::new(config.clone(), &working_dir, tasks_to_execute.clone());
+    let engine_options = EngineOptions {
+        continue_on_error: options.continue_on_error,
+        concurrency: options
+            .concurrency
+            .unwrap_or_else(hasty::options::default_concurrency),
+        package_manager: options.package_manager,
+        execution_mode: options.mode,
+        shutdown,
+        grace_period: Duration::from_secs(options.grace_period),
+        default_timeout: options.timeout.map(Duration::from_secs),
+        timed_out_output_lines: options.timeout_output_lines,
+    };
+
+    let mut engine = Engine::new(
+        config,
+        &working_dir,
+        tasks_to_execute.clone(),
+        engine_options,
+    )?;
 
     for task in tasks_to_execute.iter() {
-        let script = Script::new(
-            config.pipeline.get(task).unwrap().clone(),
-            &working_dir,
-            "__ROOT__",
-        );
+        let script = root_script(&engine, task, None)?;
 
         engine.add_script(&script);
 
@@ -45,11 +202,11 @@ async fn main() {
         if script.has_dependencies() {
             let mut stack = vec![];
 
-            stack.append(&mut script.dependencies().unwrap());
-
-            while stack.len() > 0 {
-                let s = stack.pop().unwrap();
+            for d in script.dependencies().unwrap_or_default() {
+                stack.push((d, task.clone()));
+            }
 
+            while let Some((s, dependent)) = stack.pop() {
                 if engine
                     .scripts()
                     .contains_key(&make_script_id("__ROOT__", &s))
@@ -57,28 +214,73 @@ async fn main() {
                     continue;
                 }
 
-                let s = Script::new(
-                    config.pipeline.get(&s).unwrap().clone(),
-                    &working_dir,
-                    "__ROOT__",
-                );
+                let s = root_script(&engine, &s, Some(dependent))?;
                 engine.add_script(&s);
 
-                if s.has_dependencies() {
-                    stack.append(&mut s.dependencies().unwrap());
+                for d in s.dependencies().unwrap_or_default() {
+                    stack.push((d, s.command.clone()));
                 }
             }
         }
     }
 
-    engine.build_package_graph();
+    engine.build_package_graph()?;
 
-    engine.resolve_workspace_scripts();
+    engine.filter_packages(&options.filter);
+
+    if let Some(since) = &options.since {
+        engine.filter_changed_since(since)?;
+    }
+
+    engine.resolve_workspace_scripts()?;
 
     engine.add_topo_task_deps();
 
     // populate graph dependencies
-    engine.add_deps_to_graph();
+    engine.add_deps_to_graph()?;
+
+    Ok(engine)
+}
 
-    engine.execute(options.dry_run).await;
+fn exit_on_error<T>(result: Result<T, HastyError>) -> T {
+    match result {
+        Ok(value) => value,
+        Err(err) => {
+            error!("{}", err);
+
+            std::process::exit(1);
+        }
+    }
+}
+
+// Creates the "__ROOT__" template script for a pipeline task. A task that only has entries scoped
+// to specific packages, or only exists in packages' own hasty.json, gets a template without
+// dependencies, since each package resolves its own entry
+fn root_script(
+    engine: &Engine,
+    task: &str,
+    dependent: Option<String>,
+) -> Result<Script, HastyError> {
+    let dir = engine.dir();
+
+    match engine.config().pipeline.get(task) {
+        Some(command_config) => Ok(Script::new(command_config.clone(), dir, "__ROOT__")),
+        None if engine.defines_task(task) => Ok(Script::new(
+            CommandConfig {
+                command: String::from(task),
+                dependencies: None,
+                files: None,
+                output: None,
+                timeout: None,
+                persistent: false,
+                readiness: None,
+            },
+            dir,
+            "__ROOT__",
+        )),
+        None => Err(HastyError::UnknownTask {
+            task: String::from(task),
+            dependent,
+        }),
+    }
 }
@@ -1,20 +1,205 @@
-use std::path::PathBuf;
+use std::{path::PathBuf, str::FromStr, thread};
 
-use clap::Parser;
+use clap::{Args, Parser, Subcommand};
 use serde::Deserialize;
 
+use crate::{
+    filter::Selector,
+    graph::{GraphFormat, GraphKind},
+    package_manager::PackageManager,
+    plan::DryRun,
+    ExecutionMode,
+};
+
 #[derive(Parser)]
 #[clap(author, version, about, long_about = None)]
 #[derive(Deserialize)]
 pub struct HastyOptions {
+    #[command(subcommand)]
+    pub command: Option<HastyCommand>,
+
     /// The directory of the project
-    #[arg(short, long)]
+    #[arg(short, long, global = true)]
     pub dir: Option<PathBuf>,
 
-    /// Whether or the script should actually be executed
+    /// Report the tasks that would be executed instead of running them, as log lines or, with
+    /// `--dry-run=json`, as a JSON plan on stdout
+    #[arg(
+        long,
+        global = true,
+        value_enum,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "text"
+    )]
+    pub dry_run: Option<DryRun>,
+
+    /// Keep running every task whose dependencies succeeded after a task fails
+    #[arg(long = "continue", global = true)]
+    pub continue_on_error: bool,
+
+    /// The maximum number of tasks to run at once, as a number or a percentage of the available
+    /// CPUs (e.g. `50%`). Defaults to the available parallelism
+    #[arg(long, global = true, value_parser = parse_concurrency)]
+    pub concurrency: Option<usize>,
+
+    /// The package manager used to run scripts, detected from the workspace when omitted
+    #[arg(long, global = true, value_enum)]
+    pub package_manager: Option<PackageManager>,
+
+    /// Whether scripts run through the package manager or directly through `sh -c`
+    #[arg(long, global = true, value_enum, default_value_t = ExecutionMode::PackageManager)]
+    pub mode: ExecutionMode,
+
+    /// How many seconds tasks get to exit after hasty is interrupted before they are killed
+    #[arg(long, global = true, value_name = "SECONDS", default_value_t = 10)]
+    pub grace_period: u64,
+
+    /// How many seconds a task may run before it is stopped and reported as timed out, for tasks
+    /// without a `timeout` in hasty.json. Persistent tasks only time out with their own `timeout`
+    #[arg(long, global = true, value_name = "SECONDS")]
+    pub timeout: Option<u64>,
+
+    /// How many of a timed out task's last output lines are repeated after it is stopped
+    #[arg(long, global = true, value_name = "LINES", default_value_t = crate::TIMED_OUT_OUTPUT_LINES)]
+    pub timeout_output_lines: usize,
+
+    /// Only run the script in the selected packages: `pkg`, `@scope/*`, `./packages/a`, `pkg...`
+    /// (with its dependencies), `...pkg` (with its dependents) or `!pkg` (exclude)
+    #[arg(long, global = true, value_parser = Selector::from_str)]
+    pub filter: Vec<Selector>,
+
+    /// Only run the script in packages with changes since the given git ref, and their dependents
+    #[arg(long, global = true)]
+    pub since: Option<String>,
+
+    /// The script to execute
+    pub script: Option<String>,
+}
+
+#[derive(Subcommand, Deserialize)]
+pub enum HastyCommand {
+    /// Write the task graph or the package graph as Graphviz DOT, Mermaid or JSON
+    Graph(GraphOptions),
+    /// Run the script, and with `--watch` keep rerunning the affected tasks when files change.
+    /// Without a script or `--watch`, runs the `dev` task
+    Dev(DevOptions),
+}
+
+#[derive(Args, Deserialize)]
+pub struct DevOptions {
+    /// Rerun the tasks in changed packages, and the tasks downstream of them, on every change
     #[arg(long)]
-    pub dry_run: bool,
+    pub watch: bool,
 
     /// The script to execute
     pub script: Option<String>,
 }
+
+#[derive(Args, Deserialize)]
+pub struct GraphOptions {
+    /// Which graph to write
+    #[arg(long = "graph", value_enum, default_value_t = GraphKind::Task)]
+    pub kind: GraphKind,
+
+    /// The format to write the graph in
+    #[arg(long, value_enum, default_value_t = GraphFormat::Dot)]
+    pub format: GraphFormat,
+
+    /// The file to write the graph to instead of stdout
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+
+    /// Only include the given script and its dependencies in the task graph
+    pub script: Option<String>,
+}
+
+/// The number of tasks to run at once when no limit is given
+pub fn default_concurrency() -> usize {
+    thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+/// Parses a concurrency limit given either as an absolute number of tasks or as a percentage of
+/// the available CPUs. Percentages always allow at least one task.
+pub fn parse_concurrency(value: &str) -> Result<usize, String> {
+    let limit = match value.strip_suffix('%') {
+        Some(percent) => {
+            let percent = percent
+                .parse::<f64>()
+                .map_err(|_| format!("invalid percentage: {}", value))?;
+
+            if percent <= 0.0 {
+                return Err(String::from(
+                    "concurrency percentage must be greater than 0",
+                ));
+            }
+
+            ((default_concurrency() as f64 * percent / 100.0).floor() as usize).max(1)
+        }
+        None => value
+            .parse::<usize>()
+            .map_err(|_| format!("invalid concurrency: {}", value))?,
+    };
+
+    if limit == 0 {
+        return Err(String::from("concurrency must be at least 1"));
+    }
+
+    Ok(limit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn run_flags_are_accepted_after_the_dev_subcommand() {
+        let options = HastyOptions::try_parse_from([
+            "hasty",
+            "dev",
+            "--watch",
+            "build",
+            "--continue",
+            "--concurrency",
+            "2",
+            "--dry-run=json",
+            "--package-manager",
+            "pnpm",
+            "--mode",
+            "shell",
+            "--grace-period",
+            "3",
+            "--timeout",
+            "60",
+        ])
+        .unwrap();
+
+        assert!(options.continue_on_error);
+        assert_eq!(options.concurrency, Some(2));
+        assert!(matches!(options.dry_run, Some(DryRun::Json)));
+        assert!(matches!(
+            options.package_manager,
+            Some(PackageManager::Pnpm)
+        ));
+        assert!(matches!(options.mode, ExecutionMode::Shell));
+        assert_eq!(options.grace_period, 3);
+        assert_eq!(options.timeout, Some(60));
+
+        match options.command {
+            Some(HastyCommand::Dev(dev)) => {
+                assert!(dev.watch);
+                assert_eq!(dev.script.as_deref(), Some("build"));
+            }
+            _ => panic!("expected the dev subcommand"),
+        }
+    }
+
+    #[test]
+    fn parses_concurrency_as_a_number_or_a_percentage() {
+        assert_eq!(parse_concurrency("3"), Ok(3));
+        assert!(parse_concurrency("100%").unwrap() >= 1);
+        assert!(parse_concurrency("0").is_err());
+        assert!(parse_concurrency("0%").is_err());
+        assert!(parse_concurrency("many").is_err());
+    }
+}
@@ -0,0 +1,299 @@
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    check_command,
+    error::{HastyError, Result},
+    package_json::PackageJSON,
+    readiness::Readiness,
+    split_script_id, CommandConfig, Config, CONFIG_FILE_NAME,
+};
+
+// The value of `extends` that refers to the hasty.json at the workspace root
+static ROOT_CONFIG: &str = "//";
+
+/// A hasty.json inside a workspace package, whose pipeline entries are merged over the root
+/// pipeline for that package's scripts only
+#[derive(Debug, Deserialize, Clone)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct PackageConfig {
+    /// Must be `"//"`, the root hasty.json
+    pub extends: String,
+    #[serde(default)]
+    pub pipeline: HashMap<String, TaskOverride>,
+    /// Whether the pipeline may define tasks that the root pipeline doesn't know about
+    #[serde(default)]
+    pub allow_new_tasks: bool,
+}
+
+/// A package's changes to a pipeline entry. Every field that is set replaces the root's value.
+#[derive(Debug, Default, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct TaskOverride {
+    pub command: Option<String>,
+    pub dependencies: Option<Vec<String>>,
+    pub files: Option<Vec<String>>,
+    pub output: Option<Vec<String>>,
+    pub timeout: Option<u64>,
+    pub persistent: Option<bool>,
+    pub readiness: Option<Readiness>,
+}
+
+impl TaskOverride {
+    /// Merges the override over the root's entry for `task`, if the root has one
+    pub fn apply(&self, base: Option<&CommandConfig>, task: &str) -> CommandConfig {
+        CommandConfig {
+            command: self
+                .command
+                .clone()
+                .or_else(|| base.map(|b| b.command.clone()))
+                .unwrap_or_else(|| String::from(task)),
+            dependencies: self
+                .dependencies
+                .clone()
+                .or_else(|| base.and_then(|b| b.dependencies.clone())),
+            files: self
+                .files
+                .clone()
+                .or_else(|| base.and_then(|b| b.files.clone())),
+            output: self
+                .output
+                .clone()
+                .or_else(|| base.and_then(|b| b.output.clone())),
+            timeout: self.timeout.or_else(|| base.and_then(|b| b.timeout)),
+            persistent: self
+                .persistent
+                .unwrap_or_else(|| base.is_some_and(|b| b.persistent)),
+            readiness: self
+                .readiness
+                .clone()
+                .or_else(|| base.and_then(|b| b.readiness.clone())),
+        }
+    }
+}
+
+/// Reads the hasty.json of every workspace package that has one, keyed by package name. The root
+/// package is skipped, since its hasty.json is the root config.
+pub fn load_package_configs(
+    workspaces: &[PackageJSON],
+    root_dir: &Path,
+    root_config: &Config,
+) -> Result<HashMap<String, PackageConfig>> {
+    let mut configs = HashMap::new();
+
+    for ws in workspaces {
+        let dir = match &ws.dir {
+            Some(dir) if dir != root_dir => dir,
+            _ => continue,
+        };
+
+        if let Some(config) = read_package_config(&dir.join(CONFIG_FILE_NAME), root_config)? {
+            configs.insert(ws.name.clone(), config);
+        }
+    }
+
+    Ok(configs)
+}
+
+fn read_package_config(path: &Path, root_config: &Config) -> Result<Option<PackageConfig>> {
+    let raw = match fs::read_to_string(path) {
+        Ok(raw) => raw,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(source) => {
+            return Err(HastyError::Io {
+                path: path.to_path_buf(),
+                source,
+            })
+        }
+    };
+
+    let config: PackageConfig = serde_json::from_str(&raw).map_err(|source| HastyError::Json {
+        path: path.to_path_buf(),
+        source,
+    })?;
+
+    let invalid = |message: String| HastyError::InvalidConfig {
+        path: PathBuf::from(path),
+        message,
+    };
+
+    if config.extends != ROOT_CONFIG {
+        return Err(invalid(format!(
+            "\"extends\" must be \"{}\", the root {}",
+            ROOT_CONFIG, CONFIG_FILE_NAME
+        )));
+    }
+
+    for (task, task_override) in &config.pipeline {
+        if split_script_id(task).is_some() {
+            return Err(invalid(format!(
+                "{} can't be scoped to a package, since the config only applies to its own package",
+                task
+            )));
+        }
+
+        if !config.allow_new_tasks && !root_config.defines_task(task) {
+            return Err(invalid(format!(
+                "{} is not in the root pipeline, set \"allowNewTasks\" to add it",
+                task
+            )));
+        }
+
+        if let Some(command) = &task_override.command {
+            check_command(task, command)
+                .map_err(|message| invalid(format!("{}: {}", task, message)))?;
+        }
+    }
+
+    Ok(Some(config))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::package_json::read_package_json;
+    use tempfile::TempDir;
+
+    fn root_config() -> Config {
+        serde_json::from_str(
+            r#"{ "pipeline": {
+                "build": { "command": "build", "dependencies": ["^build"], "output": ["dist"] },
+                "web#test": { "command": "test" }
+            } }"#,
+        )
+        .unwrap()
+    }
+
+    // A workspace with package `a`, whose hasty.json holds `config` when given
+    fn create_workspace(config: Option<&str>) -> TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        let package = dir.path().join("packages/a");
+        fs::create_dir_all(&package).unwrap();
+
+        fs::write(dir.path().join("package.json"), r#"{ "name": "root" }"#).unwrap();
+        fs::write(dir.path().join(CONFIG_FILE_NAME), "{}").unwrap();
+        fs::write(package.join("package.json"), r#"{ "name": "a" }"#).unwrap();
+        if let Some(config) = config {
+            fs::write(package.join(CONFIG_FILE_NAME), config).unwrap();
+        }
+
+        dir
+    }
+
+    fn load(config: Option<&str>) -> Result<HashMap<String, PackageConfig>> {
+        let root = create_workspace(config);
+        let workspaces = vec![
+            read_package_json(&root.path().join("packages/a")).unwrap(),
+            read_package_json(root.path()).unwrap(),
+        ];
+
+        load_package_configs(&workspaces, root.path(), &root_config())
+    }
+
+    fn invalid_message(config: &str) -> String {
+        match load(Some(config)) {
+            Err(HastyError::InvalidConfig { message, .. }) => message,
+            other => panic!("expected an invalid config, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn packages_without_a_config_are_skipped() {
+        assert!(load(None).unwrap().is_empty());
+    }
+
+    #[test]
+    fn the_root_config_isnt_a_package_config() {
+        let root = create_workspace(None);
+        let workspaces = vec![read_package_json(root.path()).unwrap()];
+
+        let configs = load_package_configs(&workspaces, root.path(), &root_config()).unwrap();
+
+        assert!(configs.is_empty());
+    }
+
+    #[test]
+    fn loads_configs_by_package_name() {
+        let configs = load(Some(r#"{ "extends": "//", "pipeline": { "build": {} } }"#)).unwrap();
+
+        assert!(configs["a"].pipeline.contains_key("build"));
+    }
+
+    #[test]
+    fn extends_must_be_the_root_config() {
+        assert!(invalid_message(r#"{ "extends": "../b" }"#).contains(r#""extends" must be "//""#));
+    }
+
+    #[test]
+    fn tasks_cant_be_scoped_to_a_package() {
+        assert!(
+            invalid_message(r#"{ "extends": "//", "pipeline": { "b#build": {} } }"#)
+                .contains("b#build can't be scoped to a package")
+        );
+    }
+
+    #[test]
+    fn new_tasks_need_allow_new_tasks() {
+        let config = r#"{ "extends": "//", "pipeline": { "lint": { "command": "lint" } } }"#;
+        assert!(invalid_message(config).contains("lint is not in the root pipeline"));
+
+        let config = r#"{ "extends": "//", "allowNewTasks": true, "pipeline": { "lint": {} } }"#;
+        assert!(load(Some(config)).is_ok());
+    }
+
+    #[test]
+    fn tasks_from_package_scoped_root_entries_arent_new() {
+        assert!(load(Some(r#"{ "extends": "//", "pipeline": { "test": {} } }"#)).is_ok());
+    }
+
+    #[test]
+    fn commands_must_match_their_task() {
+        let config = r#"{ "extends": "//", "pipeline": { "build": { "command": "compile" } } }"#;
+
+        assert!(invalid_message(config).contains(r#"build: "command" must be "build""#));
+    }
+
+    #[test]
+    fn unknown_fields_are_rejected() {
+        let config = r#"{ "extends": "//", "pipeline": { "build": { "outputs": ["dist"] } } }"#;
+
+        assert!(matches!(load(Some(config)), Err(HastyError::Json { .. })));
+    }
+
+    #[test]
+    fn overrides_replace_only_the_fields_they_set() {
+        let root = root_config();
+        let task_override = TaskOverride {
+            output: Some(vec![String::from("lib")]),
+            timeout: Some(30),
+            ..TaskOverride::default()
+        };
+
+        let config = task_override.apply(root.pipeline.get("build"), "build");
+
+        assert_eq!(config.command, "build");
+        assert_eq!(config.dependencies, Some(vec![String::from("^build")]));
+        assert_eq!(config.output, Some(vec![String::from("lib")]));
+        assert_eq!(config.files, None);
+        assert_eq!(config.timeout, Some(30));
+        assert!(!config.persistent);
+    }
+
+    #[test]
+    fn overrides_of_new_tasks_default_the_command_to_the_task() {
+        let task_override = TaskOverride {
+            persistent: Some(true),
+            ..TaskOverride::default()
+        };
+
+        let config = task_override.apply(None, "dev");
+
+        assert_eq!(config.command, "dev");
+        assert_eq!(config.dependencies, None);
+        assert!(config.persistent);
+    }
+}
@@ -1,9 +1,19 @@
 use glob::glob;
-use std::{collections::HashMap, fs, path::PathBuf};
+use std::{
+    collections::{HashMap, HashSet},
+    fs, io,
+    path::{Path, PathBuf},
+};
 
 use serde::Deserialize;
 
-#[derive(Deserialize, Debug)]
+use crate::error::{HastyError, Result};
+
+static PNPM_WORKSPACE_FILE: &str = "pnpm-workspace.yaml";
+static LERNA_FILE: &str = "lerna.json";
+static LERNA_DEFAULT_GLOB: &str = "packages/*";
+
+#[derive(Deserialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct PackageJSON {
     pub name: String,
@@ -13,42 +23,342 @@ pub struct PackageJSON {
     pub dev_dependencies: Option<HashMap<String, String>>,
     pub peer_dependencies: Option<HashMap<String, String>>,
     pub optional_dependencies: Option<HashMap<String, String>>,
-    pub workspaces: Option<Vec<String>>,
+    pub workspaces: Option<Workspaces>,
     pub private: Option<bool>,
+    pub package_manager: Option<String>,
 
     #[serde(skip)]
     pub dir: Option<PathBuf>,
 }
 
-pub fn read_package_json(path: &PathBuf) -> PackageJSON {
-    let raw = fs::read_to_string(path.join("package.json")).unwrap();
-    let mut pkg: PackageJSON = serde_json::from_str(&raw).unwrap();
+/// The `workspaces` field of a package.json: either a list of globs, or yarn's object form that
+/// also lists the packages that shouldn't be hoisted
+#[derive(Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum Workspaces {
+    Globs(Vec<String>),
+    Yarn {
+        #[serde(default)]
+        packages: Vec<String>,
+        nohoist: Option<Vec<String>>,
+    },
+}
+
+impl Workspaces {
+    pub fn globs(&self) -> &[String] {
+        match self {
+            Workspaces::Globs(globs) => globs,
+            Workspaces::Yarn { packages, .. } => packages,
+        }
+    }
+}
+
+// The `packages` list shared by pnpm-workspace.yaml and lerna.json
+#[derive(Deserialize, Debug, Default)]
+struct WorkspaceFile {
+    #[serde(default)]
+    packages: Vec<String>,
+}
+
+impl PackageJSON {
+    /// Every dependency of the package with its version range, from `dependencies`,
+    /// `devDependencies`, `peerDependencies` and `optionalDependencies` in that order
+    pub fn all_dependencies(&self) -> impl Iterator<Item = (&String, &String)> {
+        [
+            &self.dependencies,
+            &self.dev_dependencies,
+            &self.peer_dependencies,
+            &self.optional_dependencies,
+        ]
+        .into_iter()
+        .flatten()
+        .flat_map(|deps| deps.iter())
+    }
+}
+
+pub fn read_package_json(path: &Path) -> Result<PackageJSON> {
+    let file = path.join("package.json");
 
-    pkg.dir = Some(path.clone());
+    let raw = fs::read_to_string(&file).map_err(|source| HastyError::Io {
+        path: file.clone(),
+        source,
+    })?;
+    let mut pkg: PackageJSON =
+        serde_json::from_str(&raw).map_err(|source| HastyError::Json { path: file, source })?;
 
-    return pkg;
+    pkg.dir = Some(path.to_path_buf());
+
+    Ok(pkg)
 }
 
-pub fn find_workspaces(root_dir: &PathBuf) -> Vec<PackageJSON> {
+/// Finds the workspace packages of the repository at `root_dir`, followed by the root package
+/// itself. The workspace globs come from pnpm-workspace.yaml, the root package.json's `workspaces`
+/// or lerna.json, in that order of preference. Globs prefixed with `!` exclude the directories
+/// they match, and matched directories without a package.json are skipped.
+pub fn find_workspaces(root_dir: &Path) -> Result<Vec<PackageJSON>> {
     let mut result: Vec<PackageJSON> = vec![];
 
-    let pkg = read_package_json(root_dir);
-
-    if let Some(workspaces) = &pkg.workspaces {
-        for ws in workspaces {
-            let glob_with_root = root_dir.join(&ws);
-            for entry in glob(glob_with_root.to_str().unwrap()).unwrap() {
-                match entry {
-                    Ok(p) => {
-                        result.push(read_package_json(&p));
-                    }
-                    Err(e) => panic!("{}", e),
-                }
+    let pkg = read_package_json(root_dir)?;
+
+    let globs = match read_pnpm_workspace(root_dir)? {
+        Some(file) => file.packages,
+        None => match &pkg.workspaces {
+            Some(workspaces) => workspaces.globs().to_vec(),
+            // lerna falls back to `packages/*` when it doesn't list any packages
+            None => match read_lerna_file(root_dir)? {
+                Some(file) if file.packages.is_empty() => vec![String::from(LERNA_DEFAULT_GLOB)],
+                Some(file) => file.packages,
+                None => vec![],
+            },
+        },
+    };
+
+    let (excludes, includes): (Vec<&String>, Vec<&String>) =
+        globs.iter().partition(|g| g.starts_with('!'));
+
+    let excluded = excludes
+        .into_iter()
+        .map(|g| expand_glob(root_dir, &g[1..]))
+        .collect::<Result<Vec<Vec<PathBuf>>>>()?
+        .into_iter()
+        .flatten()
+        .collect::<HashSet<PathBuf>>();
+
+    let mut seen = HashSet::new();
+
+    for ws in includes {
+        for dir in expand_glob(root_dir, ws)? {
+            if excluded.contains(&dir) || !dir.join("package.json").is_file() {
+                continue;
+            }
+
+            // a package may be matched by more than one glob
+            if seen.insert(dir.clone()) {
+                result.push(read_package_json(&dir)?);
             }
         }
     }
 
     result.push(pkg);
 
-    result
+    Ok(result)
+}
+
+// Lists the paths under `root_dir` matched by a workspace glob. Installed packages are never
+// workspaces, so paths inside of `node_modules` are skipped, e.g. for `packages/**`.
+fn expand_glob(root_dir: &Path, pattern: &str) -> Result<Vec<PathBuf>> {
+    let pattern_with_root = root_dir
+        .join(pattern.trim_end_matches('/'))
+        .to_string_lossy()
+        .into_owned();
+    let entries = glob(&pattern_with_root).map_err(|source| HastyError::Glob {
+        pattern: String::from(pattern),
+        source,
+    })?;
+
+    let mut paths = vec![];
+
+    for entry in entries {
+        let path = entry.map_err(|e| HastyError::Io {
+            path: e.path().to_path_buf(),
+            source: io::Error::from(e),
+        })?;
+
+        let in_node_modules = path
+            .strip_prefix(root_dir)
+            .unwrap_or(&path)
+            .components()
+            .any(|c| c.as_os_str() == "node_modules");
+
+        if !in_node_modules {
+            paths.push(path);
+        }
+    }
+
+    Ok(paths)
+}
+
+// Reads pnpm-workspace.yaml from the workspace root, if it exists
+fn read_pnpm_workspace(root_dir: &Path) -> Result<Option<WorkspaceFile>> {
+    let file = root_dir.join(PNPM_WORKSPACE_FILE);
+
+    let raw = match read_optional(&file)? {
+        Some(raw) => raw,
+        None => return Ok(None),
+    };
+
+    // an empty pnpm-workspace.yaml is valid and lists no packages
+    if raw.trim().is_empty() {
+        return Ok(Some(WorkspaceFile::default()));
+    }
+
+    serde_yaml::from_str(&raw)
+        .map(Some)
+        .map_err(|source| HastyError::Yaml { path: file, source })
+}
+
+// Reads lerna.json from the workspace root, if it exists
+fn read_lerna_file(root_dir: &Path) -> Result<Option<WorkspaceFile>> {
+    let file = root_dir.join(LERNA_FILE);
+
+    let raw = match read_optional(&file)? {
+        Some(raw) => raw,
+        None => return Ok(None),
+    };
+
+    serde_json::from_str(&raw)
+        .map(Some)
+        .map_err(|source| HastyError::Json { path: file, source })
+}
+
+fn read_optional(file: &Path) -> Result<Option<String>> {
+    match fs::read_to_string(file) {
+        Ok(raw) => Ok(Some(raw)),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(source) => Err(HastyError::Io {
+            path: file.to_path_buf(),
+            source,
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    // Creates a workspace root with the given files, each package.json named after its directory
+    fn create_workspace(root_files: &[(&str, &str)], packages: &[&str]) -> TempDir {
+        let dir = tempfile::tempdir().unwrap();
+
+        write(dir.path(), "package.json", r#"{ "name": "root" }"#);
+        for (path, contents) in root_files {
+            write(dir.path(), path, contents);
+        }
+        for package in packages {
+            let name = Path::new(package).file_name().unwrap().to_string_lossy();
+            write(
+                dir.path(),
+                &format!("{}/package.json", package),
+                &format!(r#"{{ "name": "{}" }}"#, name),
+            );
+        }
+
+        dir
+    }
+
+    fn write(root: &Path, path: &str, contents: &str) {
+        let path = root.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    fn names(root: &TempDir) -> Vec<String> {
+        let mut names = find_workspaces(root.path())
+            .unwrap()
+            .into_iter()
+            .map(|pkg| pkg.name)
+            .collect::<Vec<String>>();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn npm_workspaces_are_globs_in_package_json() {
+        let root = create_workspace(
+            &[(
+                "package.json",
+                r#"{ "name": "root", "workspaces": ["packages/*", "!packages/c"] }"#,
+            )],
+            &["packages/a", "packages/b", "packages/c"],
+        );
+
+        assert_eq!(names(&root), ["a", "b", "root"]);
+    }
+
+    #[test]
+    fn yarn_workspaces_can_be_an_object() {
+        let root = create_workspace(
+            &[(
+                "package.json",
+                r#"{ "name": "root", "workspaces": { "packages": ["apps/*"], "nohoist": ["**/react"] } }"#,
+            )],
+            &["apps/web", "packages/a"],
+        );
+
+        assert_eq!(names(&root), ["root", "web"]);
+    }
+
+    #[test]
+    fn pnpm_workspace_file_wins_over_package_json() {
+        let root = create_workspace(
+            &[
+                (
+                    "package.json",
+                    r#"{ "name": "root", "workspaces": ["apps/*"] }"#,
+                ),
+                (
+                    "pnpm-workspace.yaml",
+                    "packages:\n  - 'packages/*'\n  - '!packages/b'\n",
+                ),
+            ],
+            &["apps/web", "packages/a", "packages/b"],
+        );
+
+        assert_eq!(names(&root), ["a", "root"]);
+    }
+
+    #[test]
+    fn empty_pnpm_workspace_file_lists_no_packages() {
+        let root = create_workspace(&[("pnpm-workspace.yaml", "")], &["packages/a"]);
+
+        assert_eq!(names(&root), ["root"]);
+    }
+
+    #[test]
+    fn lerna_file_lists_packages() {
+        let root = create_workspace(
+            &[("lerna.json", r#"{ "packages": ["modules/*"] }"#)],
+            &["modules/a", "packages/b"],
+        );
+
+        assert_eq!(names(&root), ["a", "root"]);
+    }
+
+    #[test]
+    fn lerna_file_defaults_to_the_packages_directory() {
+        let root = create_workspace(
+            &[("lerna.json", r#"{ "version": "independent" }"#)],
+            &["packages/a"],
+        );
+
+        assert_eq!(names(&root), ["a", "root"]);
+    }
+
+    #[test]
+    fn invalid_lerna_file_is_a_json_error() {
+        let root = create_workspace(&[("lerna.json", "packages: [a]")], &[]);
+
+        assert!(matches!(
+            find_workspaces(root.path()),
+            Err(HastyError::Json { .. })
+        ));
+    }
+
+    #[test]
+    fn recursive_globs_skip_node_modules() {
+        let root = create_workspace(
+            &[(
+                "package.json",
+                r#"{ "name": "root", "workspaces": ["packages/**"] }"#,
+            )],
+            &[
+                "packages/a",
+                "packages/a/node_modules/dep",
+                "packages/nested/b",
+            ],
+        );
+
+        assert_eq!(names(&root), ["a", "b", "root"]);
+    }
 }
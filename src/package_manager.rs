@@ -0,0 +1,74 @@
+use clap::ValueEnum;
+use serde::Deserialize;
+use std::{fmt, path::Path};
+
+use crate::package_json::PackageJSON;
+
+// Lockfiles checked, in order, when the root package.json doesn't declare a `packageManager`
+static LOCKFILES: [(&str, PackageManager); 6] = [
+    ("pnpm-lock.yaml", PackageManager::Pnpm),
+    ("yarn.lock", PackageManager::Yarn),
+    ("bun.lockb", PackageManager::Bun),
+    ("bun.lock", PackageManager::Bun),
+    ("package-lock.json", PackageManager::Npm),
+    ("npm-shrinkwrap.json", PackageManager::Npm),
+];
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum PackageManager {
+    Npm,
+    Yarn,
+    Pnpm,
+    Bun,
+}
+
+impl PackageManager {
+    /// Detects the workspace's package manager from the `packageManager` field of the root
+    /// package.json (e.g. `pnpm@8.6.0`), falling back to the lockfile in the root directory and
+    /// finally to npm.
+    pub fn detect(root_dir: &Path, root_package: Option<&PackageJSON>) -> Self {
+        let declared = root_package
+            .and_then(|pkg| pkg.package_manager.as_ref())
+            .and_then(|spec| PackageManager::from_spec(spec));
+
+        if let Some(package_manager) = declared {
+            return package_manager;
+        }
+
+        LOCKFILES
+            .iter()
+            .find(|(lockfile, _)| root_dir.join(lockfile).exists())
+            .map_or(PackageManager::Npm, |(_, package_manager)| *package_manager)
+    }
+
+    /// Parses a `packageManager` spec such as `yarn@3.2.0`, ignoring the version
+    pub fn from_spec(spec: &str) -> Option<Self> {
+        let name = spec.split('@').next().unwrap_or_default();
+
+        PackageManager::from_str(name, true).ok()
+    }
+
+    /// The binary used to invoke the package manager
+    pub fn binary(&self) -> &'static str {
+        match self {
+            PackageManager::Npm => "npm",
+            PackageManager::Yarn => "yarn",
+            PackageManager::Pnpm => "pnpm",
+            PackageManager::Bun => "bun",
+        }
+    }
+
+    /// The arguments that run a package.json script. `<pm> run <script>` works for every
+    /// supported manager, and unlike yarn's `yarn <script>` shorthand it can't clash with a
+    /// builtin command of the same name.
+    pub fn run_args(&self, script: &str) -> Vec<String> {
+        vec![String::from("run"), String::from(script)]
+    }
+}
+
+impl fmt::Display for PackageManager {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.binary())
+    }
+}
@@ -0,0 +1,39 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+use crate::readiness::Readiness;
+
+/// How a dry run reports the tasks that would be executed
+#[derive(Debug, Default, Deserialize, Clone, Copy, PartialEq, Eq, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum DryRun {
+    /// An `execute:` log line for each task as the scheduler reaches it
+    #[default]
+    Text,
+    /// The whole plan as a JSON array on stdout
+    Json,
+}
+
+/// Everything a run would do for a single task, as printed by `--dry-run=json`
+#[derive(Debug, Serialize)]
+pub struct TaskPlan {
+    pub task: String,
+    pub package: String,
+    /// The package directory relative to the workspace root
+    pub dir: PathBuf,
+    /// The program and arguments the task is started with
+    pub command: Vec<String>,
+    /// The script body from the package's package.json
+    pub script: Option<String>,
+    /// The ids of the tasks that must finish first, sorted
+    pub dependencies: Vec<String>,
+    /// The ids of the tasks that wait on this one, sorted
+    pub dependents: Vec<String>,
+    pub files: Option<Vec<String>>,
+    pub output: Option<Vec<String>>,
+    pub persistent: bool,
+    pub readiness: Option<Readiness>,
+    pub timeout: Option<u64>,
+    pub hash: String,
+}
@@ -0,0 +1,164 @@
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    future::{pending, Future},
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+use tokio::{net::TcpStream, sync::mpsc, time::sleep};
+
+// How often port and file probes check their condition
+static POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A condition that marks a running task as ready, at which point the tasks that depend on it are
+/// started without waiting for it to exit
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "lowercase", deny_unknown_fields)]
+pub enum Readiness {
+    /// A regex matched against each line of the task's stdout and stderr
+    Log(LinePattern),
+    /// A TCP port on localhost that accepts connections
+    Port(u16),
+    /// A file, relative to the task's package, that is created or written to after the task
+    /// starts
+    File(PathBuf),
+}
+
+impl Readiness {
+    /// Resolves once the condition holds for a task running in `dir`. `lines` receives the
+    /// task's output as it is printed. Never resolves if the condition is never met. Call it
+    /// before starting the task, since a file probe removes the file left behind by an earlier
+    /// run at that point.
+    pub fn wait(
+        self,
+        dir: PathBuf,
+        mut lines: mpsc::UnboundedReceiver<String>,
+    ) -> impl Future<Output = ()> {
+        // a file left behind by an earlier run doesn't mean this run is ready. Comparing its
+        // metadata alone would miss a rewrite within the same mtime tick, so it is removed, and
+        // only remembered in case it can't be.
+        let stale = match &self {
+            Readiness::File(path) => {
+                let path = dir.join(path);
+                let _ = fs::remove_file(&path);
+
+                version(&path)
+            }
+            _ => None,
+        };
+
+        async move {
+            match self {
+                Readiness::Log(pattern) => {
+                    while let Some(line) = lines.recv().await {
+                        if pattern.0.is_match(&line) {
+                            return;
+                        }
+                    }
+
+                    pending().await
+                }
+                Readiness::Port(port) => {
+                    while TcpStream::connect(("localhost", port)).await.is_err() {
+                        sleep(POLL_INTERVAL).await;
+                    }
+                }
+                Readiness::File(path) => {
+                    let path = dir.join(path);
+
+                    loop {
+                        let current = version(&path);
+                        if current.is_some() && current != stale {
+                            return;
+                        }
+
+                        sleep(POLL_INTERVAL).await;
+                    }
+                }
+            }
+        }
+    }
+}
+
+// When the file at `path` was last written, along with its size and inode, or `None` if it
+// doesn't exist
+fn version(path: &Path) -> Option<(SystemTime, u64, u64)> {
+    let metadata = fs::metadata(path).ok()?;
+
+    Some((metadata.modified().ok()?, metadata.len(), metadata.ino()))
+}
+
+/// A regex that is compiled when the config is read, so an invalid pattern is reported up front
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(try_from = "String", into = "String")]
+pub struct LinePattern(Regex);
+
+impl TryFrom<String> for LinePattern {
+    type Error = regex::Error;
+
+    fn try_from(pattern: String) -> Result<Self, Self::Error> {
+        Regex::new(&pattern).map(LinePattern)
+    }
+}
+
+impl From<LinePattern> for String {
+    fn from(pattern: LinePattern) -> Self {
+        pattern.0.as_str().to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::time::timeout;
+
+    // Long enough for a few polls of the file probe
+    static WAIT: Duration = Duration::from_millis(500);
+
+    fn file_probe(dir: &Path) -> impl Future<Output = ()> {
+        let (_, rx) = mpsc::unbounded_channel();
+
+        Readiness::File(PathBuf::from("ready")).wait(dir.to_path_buf(), rx)
+    }
+
+    #[tokio::test]
+    async fn file_probe_waits_for_the_file_to_be_created() {
+        let dir = tempfile::tempdir().unwrap();
+        let probe = file_probe(dir.path());
+
+        fs::write(dir.path().join("ready"), "").unwrap();
+
+        assert!(timeout(WAIT, probe).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn file_probe_ignores_a_file_left_by_an_earlier_run() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("ready"), "").unwrap();
+
+        let probe = file_probe(dir.path());
+        tokio::pin!(probe);
+        assert!(!dir.path().join("ready").exists());
+        assert!(timeout(WAIT, &mut probe).await.is_err());
+
+        // the same contents, possibly within the same mtime tick as the stale file
+        fs::write(dir.path().join("ready"), "").unwrap();
+        assert!(timeout(WAIT, probe).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn log_probe_waits_for_a_matching_line() {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let pattern = LinePattern::try_from(String::from("^listening on \\d+")).unwrap();
+        let probe = Readiness::Log(pattern).wait(PathBuf::new(), rx);
+        tokio::pin!(probe);
+
+        tx.send(String::from("> echo listening on 3000")).unwrap();
+        assert!(timeout(WAIT, &mut probe).await.is_err());
+
+        tx.send(String::from("listening on 3000")).unwrap();
+        assert!(timeout(WAIT, probe).await.is_ok());
+    }
+}
@@ -0,0 +1,497 @@
+use futures::{channel::oneshot, future::Either, stream::FuturesUnordered, FutureExt, StreamExt};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    future::Future,
+};
+
+use crate::{shutdown::Shutdown, ExecutionSummary, ScriptStatus};
+
+/// Runs a single task for the scheduler. The engine's implementation spawns the task's script,
+/// tests can provide one that resolves immediately with a canned status. Sending on `ready`
+/// releases the task's dependents before it finishes, dropping it leaves them waiting for the
+/// task's final status.
+pub trait TaskExecutor {
+    fn execute(
+        &self,
+        task_id: &str,
+        ready: oneshot::Sender<()>,
+    ) -> impl Future<Output = ScriptStatus>;
+}
+
+// What a running task reports back to the scheduler
+enum TaskEvent {
+    // the task became ready, or `None` when it finished without ever doing so
+    Ready(Option<String>),
+    Finished(String, ScriptStatus),
+}
+
+/// Dispatches tasks in dependency order. Every task tracks how many of its dependencies are
+/// still outstanding, and is pushed onto the ready queue exactly when the last one finishes or
+/// becomes ready.
+#[derive(Debug, Clone)]
+pub struct Scheduler {
+    tasks: Vec<String>,
+    indegrees: HashMap<String, usize>,
+    dependents: HashMap<String, Vec<String>>,
+    persistent: HashSet<String>,
+    shutdown: Shutdown,
+    concurrency: usize,
+    continue_on_error: bool,
+}
+
+impl Scheduler {
+    /// Creates a scheduler for `tasks` where each `(from, to)` edge means `to` depends on `from`.
+    /// Tasks that are ready at the same time are dispatched in the order they appear in `tasks`.
+    pub fn new(
+        tasks: Vec<String>,
+        edges: &[(String, String)],
+        concurrency: usize,
+        continue_on_error: bool,
+    ) -> Self {
+        let mut indegrees = tasks
+            .iter()
+            .map(|t| (t.clone(), 0))
+            .collect::<HashMap<String, usize>>();
+        let mut dependents = HashMap::<String, Vec<String>>::new();
+
+        let mut seen = HashSet::new();
+        for (from, to) in edges {
+            if !indegrees.contains_key(from) || !seen.insert((from, to)) {
+                continue;
+            }
+
+            if let Some(indegree) = indegrees.get_mut(to) {
+                *indegree += 1;
+                dependents.entry(from.clone()).or_default().push(to.clone());
+            }
+        }
+
+        Scheduler {
+            tasks,
+            indegrees,
+            dependents,
+            persistent: HashSet::new(),
+            shutdown: Shutdown::never(),
+            concurrency: concurrency.max(1),
+            continue_on_error,
+        }
+    }
+
+    /// Marks tasks that never finish on their own, such as dev servers. They are started as soon
+    /// as they are ready and don't count towards the concurrency limit, since they would hold on
+    /// to their slot for the whole run.
+    pub fn with_persistent(mut self, tasks: HashSet<String>) -> Self {
+        self.persistent = tasks;
+        self
+    }
+
+    /// Stops starting new tasks once `shutdown` receives a signal. Tasks that haven't started yet
+    /// are reported as not started, while running tasks are left to the executor to stop.
+    pub fn with_shutdown(mut self, shutdown: Shutdown) -> Self {
+        self.shutdown = shutdown;
+        self
+    }
+
+    pub async fn run<E: TaskExecutor>(&self, executor: &E) -> ExecutionSummary {
+        let mut summary = ExecutionSummary::default();
+        let mut indegrees = self.indegrees.clone();
+
+        // tasks with at least one dependency that didn't finish successfully
+        let mut blocked = HashSet::<String>::new();
+        // tasks that released their dependents by becoming ready before they finished
+        let mut released = HashSet::<String>::new();
+        // tasks that finished, whose readiness may still be reported afterwards
+        let mut finished = HashSet::<String>::new();
+        let mut bail = false;
+
+        let mut ready = self
+            .tasks
+            .iter()
+            .filter(|t| indegrees[*t] == 0)
+            .cloned()
+            .collect::<VecDeque<String>>();
+        let mut running = FuturesUnordered::new();
+        // the number of running tasks that count towards the concurrency limit
+        let mut limited = 0;
+
+        loop {
+            let mut waiting = VecDeque::new();
+
+            while let Some(task_id) = ready.pop_front() {
+                // nothing new starts once hasty has been asked to stop, which isn't a failure
+                if self.shutdown.signal().is_some() {
+                    self.release(&task_id, false, &mut indegrees, &mut blocked, &mut ready);
+                    summary.not_started.push(task_id);
+                    continue;
+                }
+
+                if bail || blocked.contains(&task_id) {
+                    self.complete(
+                        &task_id,
+                        ScriptStatus::Skipped,
+                        &mut indegrees,
+                        &mut blocked,
+                        &mut ready,
+                        &mut summary,
+                    );
+                    continue;
+                }
+
+                if !self.persistent.contains(&task_id) {
+                    if limited >= self.concurrency {
+                        waiting.push_back(task_id);
+                        continue;
+                    }
+
+                    limited += 1;
+                }
+
+                let (tx, rx) = oneshot::channel();
+                let id = task_id.clone();
+
+                running.push(Either::Left(async move {
+                    let status = executor.execute(&task_id, tx).await;
+                    TaskEvent::Finished(task_id, status)
+                }));
+                running.push(Either::Right(
+                    rx.map(move |result| TaskEvent::Ready(result.ok().map(|()| id))),
+                ));
+            }
+
+            ready = waiting;
+
+            let (task_id, status) = match running.next().await {
+                Some(TaskEvent::Finished(task_id, status)) => (task_id, status),
+                // a task that finished already released its dependents when it did
+                Some(TaskEvent::Ready(Some(task_id))) if finished.contains(&task_id) => continue,
+                Some(TaskEvent::Ready(Some(task_id))) => {
+                    self.release(&task_id, true, &mut indegrees, &mut blocked, &mut ready);
+                    released.insert(task_id);
+                    continue;
+                }
+                Some(TaskEvent::Ready(None)) => continue,
+                None => break,
+            };
+
+            if !self.persistent.contains(&task_id) {
+                limited -= 1;
+            }
+            finished.insert(task_id.clone());
+
+            let failed = matches!(status, ScriptStatus::Error | ScriptStatus::TimedOut);
+            if failed && !self.continue_on_error {
+                bail = true;
+            }
+
+            // the dependents of a task that became ready have already been released
+            if released.contains(&task_id) {
+                record(&task_id, status, &mut summary);
+                continue;
+            }
+
+            self.complete(
+                &task_id,
+                status,
+                &mut indegrees,
+                &mut blocked,
+                &mut ready,
+                &mut summary,
+            );
+        }
+
+        summary
+    }
+
+    // Records a task's final status and releases any dependents that were only waiting on it
+    fn complete(
+        &self,
+        task_id: &str,
+        status: ScriptStatus,
+        indegrees: &mut HashMap<String, usize>,
+        blocked: &mut HashSet<String>,
+        ready: &mut VecDeque<String>,
+        summary: &mut ExecutionSummary,
+    ) {
+        let succeeded = status == ScriptStatus::Finished;
+        self.release(task_id, succeeded, indegrees, blocked, ready);
+
+        record(task_id, status, summary);
+    }
+
+    // Counts a task as done for each of its dependents, queueing the ones with no dependencies
+    // left. The dependents are blocked unless the task succeeded.
+    fn release(
+        &self,
+        task_id: &str,
+        succeeded: bool,
+        indegrees: &mut HashMap<String, usize>,
+        blocked: &mut HashSet<String>,
+        ready: &mut VecDeque<String>,
+    ) {
+        for dependent in self.dependents.get(task_id).into_iter().flatten() {
+            if !succeeded {
+                blocked.insert(dependent.clone());
+            }
+
+            let indegree = indegrees.get_mut(dependent).unwrap();
+            *indegree -= 1;
+
+            if *indegree == 0 {
+                ready.push_back(dependent.clone());
+            }
+        }
+    }
+}
+
+fn record(task_id: &str, status: ScriptStatus, summary: &mut ExecutionSummary) {
+    match status {
+        ScriptStatus::Finished => summary.succeeded.push(task_id.to_string()),
+        ScriptStatus::Skipped => summary.skipped.push(task_id.to_string()),
+        ScriptStatus::Interrupted => summary.interrupted.push(task_id.to_string()),
+        ScriptStatus::TimedOut => summary.timed_out.push(task_id.to_string()),
+        ScriptStatus::Cancelled => summary.cancelled.push(task_id.to_string()),
+        _ => summary.failed.push(task_id.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shutdown::Signal;
+    use std::sync::Mutex;
+    use tokio::task::yield_now;
+
+    // Resolves every task with a canned status after yielding a few times, so tasks that are
+    // started together overlap, and records when each one starts and finishes
+    #[derive(Default)]
+    struct FakeExecutor {
+        statuses: HashMap<String, ScriptStatus>,
+        // tasks that report being ready straight away, then keep running for a while
+        ready: HashSet<String>,
+        // tasks that report being ready and finish in the same poll
+        ready_and_done: HashSet<String>,
+        events: Mutex<Vec<String>>,
+        running: Mutex<usize>,
+        max_running: Mutex<usize>,
+    }
+
+    impl FakeExecutor {
+        fn failing(mut self, task_id: &str) -> Self {
+            self.statuses
+                .insert(task_id.to_string(), ScriptStatus::Error);
+            self
+        }
+
+        fn ready_early(mut self, task_id: &str) -> Self {
+            self.ready.insert(task_id.to_string());
+            self
+        }
+
+        fn ready_and_done(mut self, task_id: &str) -> Self {
+            self.ready_and_done.insert(task_id.to_string());
+            self
+        }
+
+        fn events(&self) -> Vec<String> {
+            self.events.lock().unwrap().clone()
+        }
+
+        fn position(&self, event: &str) -> usize {
+            let events = self.events();
+            events
+                .iter()
+                .position(|e| e == event)
+                .unwrap_or_else(|| panic!("{} not in {:?}", event, events))
+        }
+    }
+
+    impl TaskExecutor for FakeExecutor {
+        async fn execute(&self, task_id: &str, ready: oneshot::Sender<()>) -> ScriptStatus {
+            self.events
+                .lock()
+                .unwrap()
+                .push(format!("start {}", task_id));
+            {
+                let mut running = self.running.lock().unwrap();
+                *running += 1;
+                let mut max_running = self.max_running.lock().unwrap();
+                *max_running = (*max_running).max(*running);
+            }
+
+            let mut yields = 3;
+            if self.ready.contains(task_id) {
+                ready.send(()).unwrap();
+                yields = 20;
+            } else if self.ready_and_done.contains(task_id) {
+                ready.send(()).unwrap();
+                yields = 0;
+            }
+
+            for _ in 0..yields {
+                yield_now().await;
+            }
+
+            *self.running.lock().unwrap() -= 1;
+            self.events.lock().unwrap().push(format!("end {}", task_id));
+
+            self.statuses
+                .get(task_id)
+                .cloned()
+                .unwrap_or(ScriptStatus::Finished)
+        }
+    }
+
+    fn tasks(ids: &[&str]) -> Vec<String> {
+        ids.iter().map(|id| id.to_string()).collect()
+    }
+
+    fn edges(edges: &[(&str, &str)]) -> Vec<(String, String)> {
+        edges
+            .iter()
+            .map(|(from, to)| (from.to_string(), to.to_string()))
+            .collect()
+    }
+
+    fn sorted(mut ids: Vec<String>) -> Vec<String> {
+        ids.sort();
+        ids
+    }
+
+    #[tokio::test]
+    async fn runs_tasks_after_their_dependencies() {
+        let scheduler = Scheduler::new(
+            tasks(&["c", "b", "a"]),
+            &edges(&[("a", "b"), ("b", "c")]),
+            4,
+            false,
+        );
+        let executor = FakeExecutor::default();
+
+        let summary = scheduler.run(&executor).await;
+
+        assert!(summary.is_success());
+        assert_eq!(summary.succeeded, tasks(&["a", "b", "c"]));
+        assert!(executor.position("end a") < executor.position("start b"));
+        assert!(executor.position("end b") < executor.position("start c"));
+    }
+
+    #[tokio::test]
+    async fn skips_the_dependents_of_a_failed_task() {
+        let scheduler = Scheduler::new(
+            tasks(&["a", "b", "c"]),
+            &edges(&[("a", "b"), ("b", "c")]),
+            4,
+            false,
+        );
+        let executor = FakeExecutor::default().failing("a");
+
+        let summary = scheduler.run(&executor).await;
+
+        assert!(!summary.is_success());
+        assert_eq!(summary.failed, tasks(&["a"]));
+        assert_eq!(sorted(summary.skipped), tasks(&["b", "c"]));
+        assert_eq!(executor.events(), tasks(&["start a", "end a"]));
+    }
+
+    #[tokio::test]
+    async fn stops_starting_tasks_after_a_failure() {
+        let scheduler = Scheduler::new(tasks(&["a", "b"]), &[], 1, false);
+        let executor = FakeExecutor::default().failing("a");
+
+        let summary = scheduler.run(&executor).await;
+
+        assert_eq!(summary.failed, tasks(&["a"]));
+        assert_eq!(summary.skipped, tasks(&["b"]));
+    }
+
+    #[tokio::test]
+    async fn continue_on_error_runs_tasks_that_dont_depend_on_the_failure() {
+        let scheduler = Scheduler::new(tasks(&["a", "b", "c"]), &edges(&[("a", "b")]), 1, true);
+        let executor = FakeExecutor::default().failing("a");
+
+        let summary = scheduler.run(&executor).await;
+
+        assert_eq!(summary.failed, tasks(&["a"]));
+        assert_eq!(summary.skipped, tasks(&["b"]));
+        assert_eq!(summary.succeeded, tasks(&["c"]));
+    }
+
+    #[tokio::test]
+    async fn runs_at_most_concurrency_tasks_at_once() {
+        let scheduler = Scheduler::new(tasks(&["a", "b", "c", "d", "e"]), &[], 2, false);
+        let executor = FakeExecutor::default();
+
+        let summary = scheduler.run(&executor).await;
+
+        assert_eq!(summary.succeeded.len(), 5);
+        assert_eq!(*executor.max_running.lock().unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn persistent_tasks_dont_count_towards_the_concurrency_limit() {
+        let scheduler = Scheduler::new(tasks(&["server", "a", "b"]), &[], 1, false)
+            .with_persistent(HashSet::from(["server".to_string()]));
+        let executor = FakeExecutor::default();
+
+        scheduler.run(&executor).await;
+
+        assert_eq!(*executor.max_running.lock().unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn ready_tasks_release_their_dependents_before_finishing() {
+        let scheduler = Scheduler::new(
+            tasks(&["server", "e2e"]),
+            &edges(&[("server", "e2e")]),
+            4,
+            false,
+        );
+        let executor = FakeExecutor::default().ready_early("server");
+
+        let summary = scheduler.run(&executor).await;
+
+        assert!(summary.is_success());
+        assert_eq!(sorted(summary.succeeded), tasks(&["e2e", "server"]));
+        assert!(executor.position("start e2e") < executor.position("end server"));
+    }
+
+    #[tokio::test]
+    async fn readiness_reported_with_the_final_status_releases_dependents_once() {
+        let scheduler = Scheduler::new(
+            tasks(&["a", "b", "c"]),
+            &edges(&[("a", "c"), ("b", "c")]),
+            4,
+            false,
+        );
+        let executor = FakeExecutor::default().ready_and_done("a");
+
+        let summary = scheduler.run(&executor).await;
+
+        assert!(summary.is_success());
+        assert!(executor.position("end b") < executor.position("start c"));
+    }
+
+    #[tokio::test]
+    async fn tasks_wait_for_dependencies_that_never_become_ready() {
+        let scheduler = Scheduler::new(tasks(&["a", "b"]), &edges(&[("a", "b")]), 4, false);
+        let executor = FakeExecutor::default();
+
+        scheduler.run(&executor).await;
+
+        assert!(executor.position("end a") < executor.position("start b"));
+    }
+
+    #[tokio::test]
+    async fn nothing_starts_after_a_signal() {
+        let scheduler = Scheduler::new(tasks(&["a", "b"]), &edges(&[("a", "b")]), 4, false)
+            .with_shutdown(Shutdown::signalled(Signal::Interrupt));
+        let executor = FakeExecutor::default();
+
+        let summary = scheduler.run(&executor).await;
+
+        assert!(!summary.is_success());
+        assert_eq!(summary.not_started, tasks(&["a", "b"]));
+        assert!(summary.skipped.is_empty());
+        assert!(executor.events().is_empty());
+    }
+}
@@ -0,0 +1,81 @@
+use std::{env, ffi::OsString, path::Path};
+
+use crate::package_json::PackageJSON;
+
+/// The lifecycle stages of a package.json script in the order they run: `pre<script>`,
+/// `<script>` and `post<script>`, each paired with its script body. Hooks are only included when
+/// the package defines them, and nothing is returned when the script itself is missing.
+pub fn lifecycle_stages(package: &PackageJSON, script: &str) -> Vec<(String, String)> {
+    let scripts = match &package.scripts {
+        Some(scripts) => scripts,
+        None => return vec![],
+    };
+
+    if !scripts.contains_key(script) {
+        return vec![];
+    }
+
+    [
+        format!("pre{}", script),
+        String::from(script),
+        format!("post{}", script),
+    ]
+    .into_iter()
+    .filter_map(|stage| {
+        scripts
+            .get(&stage)
+            .map(|body| (stage.clone(), body.clone()))
+    })
+    .collect()
+}
+
+/// The environment a package manager would give a lifecycle stage: `node_modules/.bin` of the
+/// package and of the workspace root on `PATH`, plus the `npm_*` lifecycle variables.
+pub fn lifecycle_env(
+    root_dir: &Path,
+    package: &PackageJSON,
+    stage: &str,
+    body: &str,
+) -> Vec<(String, OsString)> {
+    let mut bin_dirs = vec![];
+    if let Some(package_dir) = &package.dir {
+        bin_dirs.push(package_dir.join("node_modules/.bin"));
+    }
+    bin_dirs.push(root_dir.join("node_modules/.bin"));
+
+    if let Some(path) = env::var_os("PATH") {
+        bin_dirs.extend(env::split_paths(&path));
+    }
+
+    let mut vars = vec![
+        (String::from("npm_lifecycle_event"), OsString::from(stage)),
+        (String::from("npm_lifecycle_script"), OsString::from(body)),
+        (
+            String::from("npm_package_name"),
+            OsString::from(&package.name),
+        ),
+        (
+            String::from("INIT_CWD"),
+            env::current_dir()
+                .unwrap_or_else(|_| root_dir.to_path_buf())
+                .into_os_string(),
+        ),
+    ];
+
+    if let Some(version) = &package.version {
+        vars.push((String::from("npm_package_version"), OsString::from(version)));
+    }
+
+    if let Some(package_dir) = &package.dir {
+        vars.push((
+            String::from("npm_package_json"),
+            package_dir.join("package.json").into_os_string(),
+        ));
+    }
+
+    if let Ok(path) = env::join_paths(bin_dirs) {
+        vars.push((String::from("PATH"), path));
+    }
+
+    vars
+}
@@ -0,0 +1,193 @@
+use log::warn;
+use std::{fmt, future::pending};
+use tokio::{
+    signal::unix::{signal, SignalKind},
+    sync::watch,
+};
+
+use crate::{error::Result, HastyError};
+
+/// A signal that asks hasty to stop
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Signal {
+    Interrupt,
+    Terminate,
+}
+
+impl Signal {
+    fn raw(self) -> libc::c_int {
+        match self {
+            Signal::Interrupt => libc::SIGINT,
+            Signal::Terminate => libc::SIGTERM,
+        }
+    }
+
+    /// The exit code of a process that was stopped by the signal
+    pub fn exit_code(self) -> i32 {
+        128 + self.raw()
+    }
+}
+
+impl fmt::Display for Signal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Signal::Interrupt => write!(f, "SIGINT"),
+            Signal::Terminate => write!(f, "SIGTERM"),
+        }
+    }
+}
+
+// What hasty has been asked to do so far
+#[derive(Debug, Clone, Copy, Default)]
+struct State {
+    signal: Option<Signal>,
+    // whether a second signal asked to kill running tasks without waiting for them
+    forced: bool,
+}
+
+/// Tells every part of a run when hasty has been asked to stop. Cloning it is cheap, and every
+/// clone sees the same signal.
+#[derive(Debug, Clone)]
+pub struct Shutdown {
+    rx: watch::Receiver<State>,
+}
+
+impl Shutdown {
+    /// Starts listening for SIGINT and SIGTERM. From then on those signals no longer stop hasty
+    /// directly, so whatever is running has to wind down once `received` resolves. A second
+    /// signal resolves `forced`, for when waiting out the grace period takes too long.
+    pub fn listen() -> Result<Self> {
+        let mut interrupt = signal(SignalKind::interrupt()).map_err(HastyError::Signal)?;
+        let mut terminate = signal(SignalKind::terminate()).map_err(HastyError::Signal)?;
+
+        let (tx, rx) = watch::channel(State::default());
+
+        tokio::spawn(async move {
+            loop {
+                let received = tokio::select! {
+                    _ = interrupt.recv() => Signal::Interrupt,
+                    _ = terminate.recv() => Signal::Terminate,
+                };
+
+                let mut state = *tx.borrow();
+                if state.signal.is_none() {
+                    warn!(
+                        "{} received, stopping running tasks (send it again to kill them)",
+                        received
+                    );
+                    state.signal = Some(received);
+                } else {
+                    warn!("{} received again, killing running tasks", received);
+                    state.forced = true;
+                }
+
+                if tx.send(state).is_err() || state.forced {
+                    return;
+                }
+            }
+        });
+
+        Ok(Shutdown { rx })
+    }
+
+    /// A shutdown that never happens, for runs that don't listen for signals
+    pub fn never() -> Self {
+        let (_, rx) = watch::channel(State::default());
+
+        Shutdown { rx }
+    }
+
+    /// A shutdown that has already received `signal`
+    #[cfg(test)]
+    pub(crate) fn signalled(signal: Signal) -> Self {
+        let (_, rx) = watch::channel(State {
+            signal: Some(signal),
+            forced: false,
+        });
+
+        Shutdown { rx }
+    }
+
+    /// The signal that was received, if any
+    pub fn signal(&self) -> Option<Signal> {
+        self.rx.borrow().signal
+    }
+
+    /// Resolves with the signal once one is received, or never if none is
+    pub async fn received(&self) -> Signal {
+        let mut rx = self.rx.clone();
+
+        loop {
+            if let Some(signal) = rx.borrow_and_update().signal {
+                return signal;
+            }
+
+            if rx.changed().await.is_err() {
+                return pending().await;
+            }
+        }
+    }
+
+    /// Resolves once a second signal is received, or never if none is
+    pub async fn forced(&self) {
+        let mut rx = self.rx.clone();
+
+        loop {
+            if rx.borrow_and_update().forced {
+                return;
+            }
+
+            if rx.changed().await.is_err() {
+                return pending().await;
+            }
+        }
+    }
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Shutdown::never()
+    }
+}
+
+/// The process group a script's process leads. Each script is started in its own group, so its
+/// package manager and everything it starts can be signalled together. The whole group is killed
+/// if this is dropped before being released, e.g. when a run is cancelled.
+pub(crate) struct ProcessGroup {
+    id: Option<u32>,
+}
+
+impl ProcessGroup {
+    pub(crate) fn new(leader: Option<u32>) -> Self {
+        ProcessGroup { id: leader }
+    }
+
+    /// Sends a signal to every process in the group
+    pub(crate) fn signal(&self, signal: Signal) {
+        self.send(signal.raw());
+    }
+
+    pub(crate) fn kill(&self) {
+        self.send(libc::SIGKILL);
+    }
+
+    /// Stops tracking the group once its leader has exited, since the id may then be reused
+    pub(crate) fn release(&mut self) {
+        self.id = None;
+    }
+
+    fn send(&self, signal: libc::c_int) {
+        if let Some(id) = self.id {
+            // the group may already be gone, in which case there is nothing left to stop
+            unsafe {
+                libc::killpg(id as libc::pid_t, signal);
+            }
+        }
+    }
+}
+
+impl Drop for ProcessGroup {
+    fn drop(&mut self) {
+        self.kill();
+    }
+}
@@ -0,0 +1,238 @@
+use semver::{Version, VersionReq};
+
+static WORKSPACE_PROTOCOL: &str = "workspace:";
+// Protocols that always point at another package, never at the workspace package of that name
+static ALIAS_PROTOCOL: &str = "npm:";
+// Protocols that point at a directory on disk, which is assumed to be the workspace package
+static LOCAL_PROTOCOLS: [&str; 3] = ["file:", "link:", "portal:"];
+
+/// Whether a dependency with the version spec `spec` resolves to the workspace package of the
+/// same name, whose version is `local_version`. `workspace:*`, `workspace:^` and `workspace:~`
+/// always do, while `workspace:` ranges and plain semver ranges must be satisfied by the local
+/// version. Specs that aren't ranges, such as dist-tags, are linked like before.
+pub fn links_to_workspace(spec: &str, local_version: Option<&str>) -> bool {
+    let spec = spec.trim();
+
+    if is_alias(spec) {
+        return false;
+    }
+
+    if LOCAL_PROTOCOLS.iter().any(|p| spec.starts_with(p)) {
+        return true;
+    }
+
+    let range = match spec.strip_prefix(WORKSPACE_PROTOCOL) {
+        Some("*" | "^" | "~" | "") => return true,
+        Some(range) => range,
+        None => spec,
+    };
+
+    let local_version = match local_version.and_then(|v| Version::parse(v.trim()).ok()) {
+        Some(x) => x,
+        None => return true,
+    };
+
+    satisfies(range, &local_version).unwrap_or(true)
+}
+
+/// Whether the spec installs a differently named package under the dependency's name, e.g.
+/// `npm:other@^1.0.0`
+pub fn is_alias(spec: &str) -> bool {
+    spec.trim().starts_with(ALIAS_PROTOCOL)
+}
+
+/// Checks `version` against an npm version range, or returns `None` when the range can't be
+/// parsed
+pub fn satisfies(range: &str, version: &Version) -> Option<bool> {
+    let mut matched = false;
+
+    for alternative in range.split("||") {
+        matched |= to_version_req(alternative.trim())?.matches(version);
+    }
+
+    Some(matched)
+}
+
+// Translates a single npm range, without `||`, into the equivalent semver requirement. npm
+// separates comparators with spaces instead of commas, supports hyphen ranges, and treats a
+// bare version as exact and a bare partial version like `1.2` as `~1.2`.
+fn to_version_req(range: &str) -> Option<VersionReq> {
+    if range.is_empty() || range == "latest" {
+        return Some(VersionReq::STAR);
+    }
+
+    let comparators = match range.split_once(" - ") {
+        Some((low, high)) => vec![format!(">={}", low.trim()), format!("<={}", high.trim())],
+        None => {
+            let mut comparators = vec![];
+            let mut operator = String::new();
+
+            // npm allows a space between an operator and its version, e.g. `>= 1.2.3`
+            for token in range.split_whitespace() {
+                if token.chars().all(|c| "<>=~^".contains(c)) {
+                    operator.push_str(token);
+                    continue;
+                }
+
+                // a bare version only means an exact or tilde range when it has no operator
+                if operator.is_empty() {
+                    comparators.push(bare_version(token));
+                } else {
+                    let version = token.strip_prefix('v').unwrap_or(token);
+                    comparators.push(format!("{}{}", operator, version));
+                    operator.clear();
+                }
+            }
+
+            comparators
+        }
+    };
+
+    VersionReq::parse(&comparators.join(", ")).ok()
+}
+
+fn bare_version(token: &str) -> String {
+    let version = token.strip_prefix('v').unwrap_or(token);
+
+    if !version.starts_with(|c: char| c.is_ascii_digit()) {
+        return String::from(token);
+    }
+
+    let is_wildcard = |part: &str| matches!(part, "x" | "X" | "*");
+    let parts = version.split('.').collect::<Vec<&str>>();
+
+    if parts.len() >= 3 && !parts.iter().any(|p| is_wildcard(p)) {
+        format!("={}", version)
+    } else if parts.iter().any(|p| is_wildcard(p)) {
+        String::from(version)
+    } else {
+        format!("~{}", version)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn links_to_workspace_follows_the_spec() {
+        let cases = [
+            // (spec, local version, links)
+            ("workspace:*", Some("1.0.0"), true),
+            ("workspace:^", Some("1.0.0"), true),
+            ("workspace:~", None, true),
+            ("workspace:", Some("1.0.0"), true),
+            ("workspace:^1.0.0", Some("1.2.0"), true),
+            ("workspace:^2.0.0", Some("1.2.0"), false),
+            ("link:../a", Some("1.0.0"), true),
+            ("file:../a", None, true),
+            ("portal:../a", Some("1.0.0"), true),
+            ("npm:other@^1.0.0", Some("1.0.0"), false),
+            ("*", Some("3.1.4"), true),
+            ("^1.2.0", Some("1.4.0"), true),
+            ("^1.2.0", Some("2.0.0"), false),
+            (">=1.0.0 <2.0.0", Some("1.9.9"), true),
+            ("1.0.0 - 1.5.0", Some("1.6.0"), false),
+            ("^1.0.0 || ^2.0.0", Some("2.3.0"), true),
+            ("^1.0.0", Some("1.1.0-beta.1"), false),
+            ("^1.1.0-beta.0", Some("1.1.0-beta.1"), true),
+            ("latest", Some("1.0.0"), true),
+            ("next", Some("1.0.0"), true),
+            ("^2.0.0", Some("not a version"), true),
+            ("^2.0.0", None, true),
+        ];
+
+        for (spec, local_version, links) in cases {
+            assert_eq!(
+                links_to_workspace(spec, local_version),
+                links,
+                "{} with local version {:?}",
+                spec,
+                local_version
+            );
+        }
+    }
+
+    #[test]
+    fn satisfies_npm_ranges() {
+        let cases = [
+            // (range, version, satisfied)
+            ("*", "1.0.0", Some(true)),
+            ("", "1.0.0", Some(true)),
+            ("1.2.3", "1.2.3", Some(true)),
+            ("1.2.3", "1.2.4", Some(false)),
+            ("v1.2.3", "1.2.3", Some(true)),
+            ("1.2", "1.2.9", Some(true)),
+            ("1.2", "1.3.0", Some(false)),
+            ("1.x", "1.9.0", Some(true)),
+            ("1.x", "2.0.0", Some(false)),
+            ("~1.2.3", "1.2.9", Some(true)),
+            ("~1.2.3", "1.3.0", Some(false)),
+            (">= 1.2.3 < 2", "1.5.0", Some(true)),
+            (">=1.2.3 <2.0.0", "2.0.0", Some(false)),
+            ("1.0.0 - 2.0.0", "2.0.0", Some(true)),
+            ("<1.0.0 || >=3.0.0", "2.0.0", Some(false)),
+            ("<1.0.0 || >=3.0.0", "3.0.0", Some(true)),
+            ("^1.0.0", "1.2.0-rc.1", Some(false)),
+            (">=1.2.0-rc.0", "1.2.0-rc.1", Some(true)),
+            ("not a range", "1.0.0", None),
+        ];
+
+        for (range, version, satisfied) in cases {
+            let parsed = Version::parse(version).unwrap();
+            assert_eq!(
+                satisfies(range, &parsed),
+                satisfied,
+                "{} against {}",
+                range,
+                version
+            );
+        }
+    }
+
+    #[test]
+    fn to_version_req_translates_npm_syntax() {
+        let cases = [
+            // (npm range, semver requirement)
+            ("", Some("*")),
+            ("latest", Some("*")),
+            ("1.2.3", Some("=1.2.3")),
+            ("1.2", Some("~1.2")),
+            ("^1.2.3", Some("^1.2.3")),
+            (">= 1.2.3 < 2", Some(">=1.2.3, <2")),
+            ("1.2.3 - 2.3.4", Some(">=1.2.3, <=2.3.4")),
+            ("1.2.3-beta.1", Some("=1.2.3-beta.1")),
+            ("next", None),
+        ];
+
+        for (range, req) in cases {
+            assert_eq!(
+                to_version_req(range),
+                req.map(|r| VersionReq::parse(r).unwrap()),
+                "{}",
+                range
+            );
+        }
+    }
+
+    #[test]
+    fn bare_version_only_changes_plain_versions() {
+        let cases = [
+            // (token, comparator)
+            ("1.2.3", "=1.2.3"),
+            ("v1.2.3", "=1.2.3"),
+            ("1.2.3-alpha.2", "=1.2.3-alpha.2"),
+            ("1.2", "~1.2"),
+            ("1", "~1"),
+            ("1.x", "1.x"),
+            ("1.2.*", "1.2.*"),
+            ("^1.2.3", "^1.2.3"),
+            ("*", "*"),
+            ("next", "next"),
+        ];
+
+        for (token, comparator) in cases {
+            assert_eq!(bare_version(token), comparator, "{}", token);
+        }
+    }
+}
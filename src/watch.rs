@@ -0,0 +1,428 @@
+use glob::Pattern;
+use log::{error, info, warn};
+use notify::{
+    Config as WatcherConfig, Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher,
+};
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tokio::{sync::mpsc, task::JoinHandle, time::timeout};
+
+use crate::{error::Result, Engine, ExecutionSummary, HastyError, RunControl, Script};
+
+// How long the watcher waits for the file system to settle before rerunning tasks
+static DEBOUNCE: Duration = Duration::from_millis(200);
+
+// Directories whose changes never trigger a run
+static IGNORED_DIRS: [&str; 2] = ["node_modules", ".git"];
+
+// A workspace package being watched
+struct WatchedPackage {
+    name: String,
+    dir: PathBuf,
+}
+
+// A run of a set of tasks that may still be in flight. `tasks` only holds the tasks that no later
+// run has taken over.
+struct Run {
+    tasks: HashSet<String>,
+    control: RunControl,
+    handle: JoinHandle<()>,
+}
+
+/// Runs every task, then watches every workspace package and reruns the tasks affected by each
+/// batch of changes, along with the tasks downstream of them. Affected tasks that an earlier run
+/// hasn't finished yet are cancelled there and run again, while its other tasks keep running.
+/// Returns once the engine's shutdown receives a signal and the runs in flight have stopped, or
+/// when watching fails.
+pub async fn watch(engine: &Engine, dry_run: bool) -> Result<()> {
+    let (tx, mut rx) = mpsc::unbounded_channel();
+
+    let mut watcher = RecommendedWatcher::new(
+        move |event| {
+            let _ = tx.send(event);
+        },
+        WatcherConfig::default().with_follow_symlinks(false),
+    )
+    .map_err(HastyError::Watch)?;
+
+    let packages = watched_packages(engine)?;
+    let root_dir = canonicalize(engine.dir())?;
+
+    for package in &packages {
+        // the root package's directory contains every other package, so only the files directly
+        // inside of it are watched for the root package
+        let mode = if package.dir == root_dir {
+            RecursiveMode::NonRecursive
+        } else {
+            RecursiveMode::Recursive
+        };
+
+        watcher
+            .watch(&package.dir, mode)
+            .map_err(HastyError::Watch)?;
+    }
+
+    info!("watching {} packages for changes", packages.len());
+
+    // the first run covers every task and is spawned like the later ones, so persistent tasks
+    // don't keep changes from being picked up
+    let tasks = engine
+        .task_graph()
+        .raw_nodes()
+        .iter()
+        .map(|node| node.weight.clone())
+        .collect::<HashSet<String>>();
+    let control = RunControl::default();
+    let run = engine.run_tasks(None, dry_run, control.clone())?;
+    let handle = tokio::spawn(async move { report(run.await) });
+
+    let mut runs = vec![Run {
+        tasks,
+        control,
+        handle,
+    }];
+
+    loop {
+        let changed = tokio::select! {
+            changed = next_changes(&mut rx) => match changed {
+                Some(x) => x,
+                None => break,
+            },
+            _ = engine.shutdown().received() => break,
+        };
+
+        let tasks = affected_tasks(engine, &packages, &changed);
+        if tasks.is_empty() {
+            continue;
+        }
+
+        runs.retain(|run| !run.handle.is_finished());
+
+        let tasks = tasks_to_rerun(engine, &runs, tasks);
+
+        for run in &mut runs {
+            let cancelled = run
+                .tasks
+                .intersection(&tasks)
+                .cloned()
+                .collect::<HashSet<String>>();
+
+            run.control.cancel(&cancelled);
+            run.tasks.retain(|task| !cancelled.contains(task));
+        }
+
+        let mut sorted = tasks.iter().cloned().collect::<Vec<String>>();
+        sorted.sort();
+        info!("change detected, running {}", sorted.join(", "));
+
+        let control = RunControl::default();
+        let run = engine.run_tasks(Some(&tasks), dry_run, control.clone())?;
+        let handle = tokio::spawn(async move { report(run.await) });
+
+        runs.push(Run {
+            tasks,
+            control,
+            handle,
+        });
+    }
+
+    // the runs stop their own tasks when a signal is received
+    for run in runs {
+        let _ = run.handle.await;
+    }
+
+    Ok(())
+}
+
+// Waits for a change and then collects every change until none arrive for the debounce period.
+// Returns `None` once the watcher is gone.
+async fn next_changes(
+    rx: &mut mpsc::UnboundedReceiver<notify::Result<Event>>,
+) -> Option<Vec<PathBuf>> {
+    let mut paths = vec![];
+
+    loop {
+        let event = if paths.is_empty() {
+            rx.recv().await?
+        } else {
+            match timeout(DEBOUNCE, rx.recv()).await {
+                Ok(Some(event)) => event,
+                Ok(None) | Err(_) => return Some(paths),
+            }
+        };
+
+        match event {
+            Ok(event) if !matches!(event.kind, EventKind::Access(_)) => paths.extend(event.paths),
+            Ok(_) => {}
+            Err(err) => warn!("error watching for changes: {}", err),
+        }
+    }
+}
+
+// Adds to the affected tasks the tasks that earlier runs haven't settled yet and that any of them
+// depend on, along with everything downstream, until nothing changes. A rerun task doesn't wait
+// for tasks outside of its run, so it would otherwise start before its dependencies are done.
+fn tasks_to_rerun(engine: &Engine, runs: &[Run], affected: HashSet<String>) -> HashSet<String> {
+    let mut tasks = affected;
+
+    loop {
+        let upstream = runs
+            .iter()
+            .flat_map(|run| {
+                run.tasks
+                    .iter()
+                    .filter(|task| !tasks.contains(*task) && !run.control.is_settled(task))
+            })
+            .filter(|task| !engine.downstream_tasks([task.as_str()]).is_disjoint(&tasks))
+            .cloned()
+            .collect::<Vec<String>>();
+
+        if upstream.is_empty() {
+            return tasks;
+        }
+
+        tasks = engine.downstream_tasks(tasks.iter().chain(&upstream).map(String::as_str));
+    }
+}
+
+// Maps changed files to the tasks of the packages that own them, plus everything downstream
+fn affected_tasks(
+    engine: &Engine,
+    packages: &[WatchedPackage],
+    changed: &[PathBuf],
+) -> HashSet<String> {
+    let changed = changed_tasks(packages, engine.scripts().values(), changed);
+
+    engine.downstream_tasks(changed.iter().map(String::as_str))
+}
+
+// The tasks of the packages that own the changed files. A task with `files` globs is only affected
+// by files that match them, and files matching any of the package's `output` globs are ignored,
+// so builds don't trigger themselves.
+fn changed_tasks<'a>(
+    packages: &[WatchedPackage],
+    scripts: impl Iterator<Item = &'a Script> + Clone,
+    changed: &[PathBuf],
+) -> HashSet<String> {
+    let mut affected = HashSet::new();
+
+    for file in changed {
+        if file
+            .components()
+            .any(|c| IGNORED_DIRS.iter().any(|d| c.as_os_str() == *d))
+        {
+            continue;
+        }
+
+        // packages are sorted deepest first, so nested packages win over their parents
+        let package = match packages.iter().find(|p| file.starts_with(&p.dir)) {
+            Some(x) => x,
+            None => continue,
+        };
+        let relative = file.strip_prefix(&package.dir).unwrap_or(file);
+
+        let scripts = scripts
+            .clone()
+            .filter(|s| s.package_name == package.name)
+            .collect::<Vec<&Script>>();
+
+        let is_output = scripts
+            .iter()
+            .flat_map(|s| s.config().output.iter().flatten())
+            .any(|pattern| matches_glob(pattern, relative));
+        if is_output {
+            continue;
+        }
+
+        for script in scripts {
+            let is_input = match &script.config().files {
+                Some(patterns) => patterns.iter().any(|p| matches_glob(p, relative)),
+                None => true,
+            };
+
+            if is_input {
+                affected.insert(script.id());
+            }
+        }
+    }
+
+    affected
+}
+
+// Matches a path relative to a package against one of its globs. A glob naming a directory, such
+// as `dist`, matches everything inside of it.
+fn matches_glob(pattern: &str, path: &Path) -> bool {
+    let pattern = pattern.trim_start_matches("./").trim_end_matches('/');
+
+    path.starts_with(pattern) || Pattern::new(pattern).is_ok_and(|p| p.matches_path(path))
+}
+
+// The packages with at least one task, sorted deepest first
+fn watched_packages(engine: &Engine) -> Result<Vec<WatchedPackage>> {
+    let mut packages = vec![];
+    let mut seen = HashSet::new();
+
+    for script in engine.scripts().values() {
+        if !seen.insert(&script.package_name) {
+            continue;
+        }
+
+        if let Some(dir) = engine
+            .workspace(&script.package_name)
+            .and_then(|ws| ws.dir.as_deref())
+        {
+            packages.push(WatchedPackage {
+                name: script.package_name.clone(),
+                dir: canonicalize(dir)?,
+            });
+        }
+    }
+
+    packages.sort_by_key(|p| std::cmp::Reverse(p.dir.components().count()));
+
+    Ok(packages)
+}
+
+fn canonicalize(dir: &Path) -> Result<PathBuf> {
+    dir.canonicalize().map_err(|source| HastyError::Io {
+        path: dir.to_path_buf(),
+        source,
+    })
+}
+
+fn report(summary: ExecutionSummary) {
+    if !summary.interrupted.is_empty() {
+        warn!("interrupted tasks: {}", summary.interrupted.join(", "));
+    } else if summary.is_success() && summary.cancelled.is_empty() {
+        info!("finished, waiting for changes");
+    } else {
+        if !summary.timed_out.is_empty() {
+            error!("timed out tasks: {}", summary.timed_out.join(", "));
+        }
+        if !summary.failed.is_empty() {
+            error!("failed tasks: {}", summary.failed.join(", "));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CommandConfig;
+
+    fn package(name: &str) -> WatchedPackage {
+        WatchedPackage {
+            name: String::from(name),
+            dir: PathBuf::from("/repo/packages").join(name),
+        }
+    }
+
+    fn script(package: &str, config: &str) -> Script {
+        let config: CommandConfig = serde_json::from_str(config).unwrap();
+
+        Script::new(
+            config,
+            &PathBuf::from("/repo/packages").join(package),
+            package,
+        )
+    }
+
+    fn changed(scripts: &[Script], files: &[&str]) -> Vec<String> {
+        let packages = [package("a"), package("b")];
+        let files = files.iter().map(PathBuf::from).collect::<Vec<PathBuf>>();
+
+        let mut tasks = changed_tasks(&packages, scripts.iter(), &files)
+            .into_iter()
+            .collect::<Vec<String>>();
+        tasks.sort();
+
+        tasks
+    }
+
+    #[test]
+    fn matches_glob_matches_directories_and_patterns() {
+        let cases = [
+            // (pattern, path, matches)
+            ("dist", "dist/index.js", true),
+            ("./dist/", "dist/nested/index.js", true),
+            ("dist", "distribution/index.js", false),
+            ("src/**/*.ts", "src/lib/index.ts", true),
+            ("src/**/*.ts", "src/lib/index.js", false),
+            ("*.json", "package.json", true),
+            ("[invalid", "src/index.ts", false),
+        ];
+
+        for (pattern, path, matches) in cases {
+            assert_eq!(
+                matches_glob(pattern, Path::new(path)),
+                matches,
+                "{} against {}",
+                pattern,
+                path
+            );
+        }
+    }
+
+    #[test]
+    fn changes_affect_the_tasks_of_their_package() {
+        let scripts = [
+            script("a", r#"{ "command": "build" }"#),
+            script("a", r#"{ "command": "test" }"#),
+            script("b", r#"{ "command": "build" }"#),
+        ];
+
+        assert_eq!(
+            changed(&scripts, &["/repo/packages/a/src/index.ts"]),
+            ["a#build", "a#test"]
+        );
+        assert!(changed(&scripts, &["/elsewhere/index.ts"]).is_empty());
+    }
+
+    #[test]
+    fn output_changes_are_ignored() {
+        let scripts = [
+            script("a", r#"{ "command": "build", "output": ["dist/**"] }"#),
+            script("a", r#"{ "command": "test" }"#),
+        ];
+
+        assert!(changed(&scripts, &["/repo/packages/a/dist/index.js"]).is_empty());
+        assert_eq!(
+            changed(&scripts, &["/repo/packages/a/src/index.ts"]),
+            ["a#build", "a#test"]
+        );
+    }
+
+    #[test]
+    fn tasks_with_files_only_see_matching_changes() {
+        let scripts = [
+            script("a", r#"{ "command": "build", "files": ["src/**"] }"#),
+            script("a", r#"{ "command": "lint" }"#),
+        ];
+
+        assert_eq!(
+            changed(&scripts, &["/repo/packages/a/README.md"]),
+            ["a#lint"]
+        );
+        assert_eq!(
+            changed(&scripts, &["/repo/packages/a/src/index.ts"]),
+            ["a#build", "a#lint"]
+        );
+    }
+
+    #[test]
+    fn ignored_directories_never_affect_tasks() {
+        let scripts = [script("a", r#"{ "command": "build" }"#)];
+
+        assert!(changed(
+            &scripts,
+            &[
+                "/repo/packages/a/node_modules/dep/index.js",
+                "/repo/packages/a/.git/HEAD"
+            ]
+        )
+        .is_empty());
+    }
+}
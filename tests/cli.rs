@@ -0,0 +1,295 @@
+use std::{
+    fs,
+    path::Path,
+    process::{Command, Output, Stdio},
+    time::{Duration, Instant},
+};
+use tempfile::TempDir;
+
+// Creates a workspace where `a` asks for a version of `b` that the local `b` doesn't satisfy,
+// which makes hasty warn while building the package graph
+fn create_mismatched_workspace() -> TempDir {
+    let dir = tempfile::tempdir().unwrap();
+
+    write(
+        dir.path(),
+        "package.json",
+        r#"{ "name": "root", "workspaces": ["packages/*"] }"#,
+    );
+    write(
+        dir.path(),
+        "hasty.json",
+        r#"{ "pipeline": { "build": { "command": "build" } } }"#,
+    );
+    write(
+        dir.path(),
+        "packages/a/package.json",
+        r#"{ "name": "a", "version": "1.0.0", "scripts": { "build": "echo a" }, "dependencies": { "b": "^2.0.0" } }"#,
+    );
+    write(
+        dir.path(),
+        "packages/b/package.json",
+        r#"{ "name": "b", "version": "1.0.0", "scripts": { "build": "echo b" } }"#,
+    );
+
+    dir
+}
+
+fn write(root: &Path, path: &str, contents: &str) {
+    let path = root.join(path);
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(path, contents).unwrap();
+}
+
+fn hasty(dir: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_hasty"))
+        .arg("-d")
+        .arg(dir)
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn graph_json_is_not_mixed_with_warnings() {
+    let workspace = create_mismatched_workspace();
+
+    let output = hasty(workspace.path(), &["graph", "build", "--format", "json"]);
+
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("b@^2.0.0"));
+    serde_json::from_slice::<serde_json::Value>(&output.stdout).unwrap();
+}
+
+#[test]
+fn dry_run_json_is_not_mixed_with_warnings() {
+    let workspace = create_mismatched_workspace();
+
+    let output = hasty(workspace.path(), &["--dry-run=json", "build"]);
+
+    assert!(output.status.success());
+    let plan = serde_json::from_slice::<Vec<serde_json::Value>>(&output.stdout).unwrap();
+    assert_eq!(plan.len(), 2);
+}
+
+#[test]
+fn dry_run_json_exits_cleanly_when_stdout_is_closed() {
+    let workspace = create_mismatched_workspace();
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_hasty"))
+        .arg("-d")
+        .arg(workspace.path())
+        .args(["--dry-run=json", "build"])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    // close the read end before hasty writes the plan, like `| head` does once it has enough
+    drop(child.stdout.take());
+
+    let output = child.wait_with_output().unwrap();
+
+    assert!(output.status.success());
+    assert!(!String::from_utf8_lossy(&output.stderr).contains("panicked"));
+}
+
+#[test]
+fn topological_dependencies_link_every_upstream_package() {
+    let dir = tempfile::tempdir().unwrap();
+    write(
+        dir.path(),
+        "package.json",
+        r#"{ "name": "root", "workspaces": ["packages/*"] }"#,
+    );
+    write(
+        dir.path(),
+        "hasty.json",
+        r#"{ "pipeline": { "build": { "command": "build", "dependencies": ["^build"] } } }"#,
+    );
+    write(
+        dir.path(),
+        "packages/a/package.json",
+        r#"{ "name": "a", "scripts": { "build": "echo a" }, "dependencies": { "b": "*", "c": "*", "d": "*" } }"#,
+    );
+    for name in ["b", "c", "d"] {
+        write(
+            dir.path(),
+            &format!("packages/{}/package.json", name),
+            &format!(
+                r#"{{ "name": "{}", "scripts": {{ "build": "echo {}" }} }}"#,
+                name, name
+            ),
+        );
+    }
+
+    let output = hasty(dir.path(), &["--dry-run=json", "build"]);
+
+    assert!(output.status.success());
+    let plan = serde_json::from_slice::<Vec<serde_json::Value>>(&output.stdout).unwrap();
+    let a = plan.iter().find(|task| task["task"] == "a#build").unwrap();
+    assert_eq!(
+        a["dependencies"],
+        serde_json::json!(["b#build", "c#build", "d#build"])
+    );
+}
+
+#[test]
+fn output_after_invalid_utf8_is_still_logged() {
+    let dir = tempfile::tempdir().unwrap();
+    write(
+        dir.path(),
+        "package.json",
+        r#"{ "name": "root", "scripts": { "build": "printf 'before\\n\\377\\nafter\\n'" } }"#,
+    );
+    write(
+        dir.path(),
+        "hasty.json",
+        r#"{ "pipeline": { "build": { "command": "build" } } }"#,
+    );
+
+    let output = hasty(dir.path(), &["--mode", "shell", "build"]);
+
+    assert!(output.status.success());
+    let logs = String::from_utf8_lossy(&output.stdout);
+    assert!(logs.contains("before"));
+    assert!(logs.contains("after"));
+}
+
+#[test]
+fn timed_out_tasks_repeat_the_end_of_their_output() {
+    let dir = tempfile::tempdir().unwrap();
+    write(
+        dir.path(),
+        "package.json",
+        r#"{ "name": "root", "scripts": { "build": "for i in $(seq 1 30); do echo line $i; done; sleep 30" } }"#,
+    );
+    write(
+        dir.path(),
+        "hasty.json",
+        r#"{ "pipeline": { "build": { "command": "build", "timeout": 1 } } }"#,
+    );
+
+    let output = hasty(
+        dir.path(),
+        &[
+            "--mode",
+            "shell",
+            "--grace-period",
+            "1",
+            "--timeout-output-lines",
+            "5",
+            "build",
+        ],
+    );
+
+    assert_eq!(output.status.code(), Some(1));
+    let logs = String::from_utf8_lossy(&output.stdout);
+    assert!(logs.contains("root#build timed out after 1s"));
+
+    let (_, tail) = logs
+        .split_once("last 5 lines of output from root#build:")
+        .unwrap();
+    for i in 26..=30 {
+        assert!(tail.contains(&format!("line {}\n", i)), "{}", tail);
+    }
+    assert!(!tail.contains("line 25\n"), "{}", tail);
+}
+
+#[test]
+fn background_processes_dont_hold_up_their_task() {
+    let dir = tempfile::tempdir().unwrap();
+    write(
+        dir.path(),
+        "package.json",
+        r#"{ "name": "root", "scripts": { "build": "sleep 5 & echo done" } }"#,
+    );
+    write(
+        dir.path(),
+        "hasty.json",
+        r#"{ "pipeline": { "build": { "command": "build" } } }"#,
+    );
+
+    let started = Instant::now();
+    let output = hasty(dir.path(), &["--mode", "shell", "build"]);
+
+    assert!(output.status.success());
+    assert!(started.elapsed() < Duration::from_secs(3));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("root#build   - done"));
+}
+
+#[test]
+fn shell_mode_runs_pre_hooks_first() {
+    // the root package of test/basic, which has a `prelint` hook
+    let dir = tempfile::tempdir().unwrap();
+    let fixture = Path::new(env!("CARGO_MANIFEST_DIR")).join("test/basic");
+    for file in ["package.json", "hasty.json"] {
+        fs::copy(fixture.join(file), dir.path().join(file)).unwrap();
+    }
+
+    let output = hasty(dir.path(), &["--mode", "shell", "lint"]);
+
+    assert!(output.status.success());
+    let logs = String::from_utf8_lossy(&output.stdout);
+    let lint = logs
+        .lines()
+        .filter(|line| line.starts_with("root#lint"))
+        .map(|line| line.split_once(" - ").unwrap().1)
+        .collect::<Vec<&str>>();
+    assert_eq!(lint, ["prelint:prelint", "lint"]);
+}
+
+#[test]
+fn package_scoped_entries_must_keep_the_task_command() {
+    let workspace = create_mismatched_workspace();
+    write(
+        workspace.path(),
+        "hasty.json",
+        r#"{ "pipeline": { "build": { "command": "build" }, "b#build": { "command": "compile" } } }"#,
+    );
+
+    let output = hasty(workspace.path(), &["--dry-run=json", "build"]);
+
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains(r#"b#build: "command" must be "build", the name of the task, not "compile""#));
+}
+
+#[test]
+fn missing_dependencies_are_reported_by_task_name() {
+    let workspace = create_mismatched_workspace();
+    write(
+        workspace.path(),
+        "hasty.json",
+        r#"{ "pipeline": { "build": { "command": "build", "dependencies": ["lint"] }, "lint": { "command": "lint", "dependencies": ["format"] } } }"#,
+    );
+
+    let output = hasty(workspace.path(), &["build"]);
+
+    assert!(!output.status.success());
+    let logs = String::from_utf8_lossy(&output.stdout);
+    assert!(logs.contains("lint depends on format, which is not defined in the pipeline"));
+    assert!(!logs.contains("__ROOT__"));
+}
+
+#[test]
+fn dev_without_a_script_runs_the_dev_task() {
+    let dir = tempfile::tempdir().unwrap();
+    write(
+        dir.path(),
+        "package.json",
+        r#"{ "name": "root", "scripts": { "dev": "echo dev", "build": "echo build" } }"#,
+    );
+    write(
+        dir.path(),
+        "hasty.json",
+        r#"{ "pipeline": { "dev": { "command": "dev" }, "build": { "command": "build" } } }"#,
+    );
+
+    let output = hasty(dir.path(), &["dev", "--dry-run=json"]);
+
+    assert!(output.status.success());
+    let plan = serde_json::from_slice::<Vec<serde_json::Value>>(&output.stdout).unwrap();
+    let tasks = plan.iter().map(|task| &task["task"]).collect::<Vec<_>>();
+    assert_eq!(tasks, ["root#dev"]);
+}
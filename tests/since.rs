@@ -0,0 +1,183 @@
+use hasty::{Config, Engine, EngineOptions};
+use std::{collections::HashSet, fs, path::Path, process::Command};
+use tempfile::TempDir;
+
+// Creates a git repo with three packages where `a` depends on `b`, and `c` is independent
+fn create_repo() -> TempDir {
+    let dir = tempfile::tempdir().unwrap();
+
+    write(
+        dir.path(),
+        "package.json",
+        r#"{ "name": "root", "workspaces": ["packages/*"] }"#,
+    );
+    write(
+        dir.path(),
+        "hasty.json",
+        r#"{ "pipeline": { "build": { "command": "build" } } }"#,
+    );
+    write(
+        dir.path(),
+        "packages/a/package.json",
+        r#"{ "name": "a", "scripts": { "build": "echo a" }, "dependencies": { "b": "*" } }"#,
+    );
+    write(
+        dir.path(),
+        "packages/b/package.json",
+        r#"{ "name": "b", "scripts": { "build": "echo b" } }"#,
+    );
+    write(
+        dir.path(),
+        "packages/c/package.json",
+        r#"{ "name": "c", "scripts": { "build": "echo c" } }"#,
+    );
+
+    git(dir.path(), &["init", "-q"]);
+    git(dir.path(), &["add", "-A"]);
+    git(dir.path(), &["commit", "-q", "-m", "initial"]);
+
+    dir
+}
+
+fn write(root: &Path, path: &str, contents: &str) {
+    let path = root.join(path);
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(path, contents).unwrap();
+}
+
+fn git(dir: &Path, args: &[&str]) {
+    let status = Command::new("git")
+        .current_dir(dir)
+        .args([
+            "-c",
+            "user.name=hasty",
+            "-c",
+            "user.email=hasty@example.com",
+        ])
+        .args(args)
+        .status()
+        .unwrap();
+
+    assert!(status.success());
+}
+
+fn affected_since(dir: &Path, since: &str) -> HashSet<String> {
+    let raw = fs::read_to_string(dir.join("hasty.json")).unwrap();
+    let config: Config = serde_json::from_str(&raw).unwrap();
+
+    let mut engine = Engine::new(
+        config,
+        dir,
+        vec![String::from("build")],
+        EngineOptions::default(),
+    )
+    .unwrap();
+    engine.build_package_graph().unwrap();
+    engine.filter_changed_since(since).unwrap();
+
+    engine.selected_packages().unwrap().clone()
+}
+
+fn names(names: &[&str]) -> HashSet<String> {
+    names.iter().map(|n| String::from(*n)).collect()
+}
+
+#[test]
+fn no_changes_affect_nothing() {
+    let repo = create_repo();
+
+    assert_eq!(affected_since(repo.path(), "HEAD"), names(&[]));
+}
+
+#[test]
+fn changed_package_includes_dependents() {
+    let repo = create_repo();
+    write(repo.path(), "packages/b/index.js", "module.exports = 1");
+
+    assert_eq!(affected_since(repo.path(), "HEAD"), names(&["a", "b"]));
+}
+
+#[test]
+fn committed_changes_since_ref_are_included() {
+    let repo = create_repo();
+    write(repo.path(), "packages/c/index.js", "module.exports = 1");
+    git(repo.path(), &["add", "-A"]);
+    git(repo.path(), &["commit", "-q", "-m", "change c"]);
+
+    assert_eq!(affected_since(repo.path(), "HEAD~1"), names(&["c"]));
+    assert_eq!(affected_since(repo.path(), "HEAD"), names(&[]));
+}
+
+#[test]
+fn root_files_affect_the_root_package() {
+    let repo = create_repo();
+    write(repo.path(), "README.md", "# hello");
+
+    assert_eq!(affected_since(repo.path(), "HEAD"), names(&["root"]));
+}
+
+#[test]
+fn global_files_affect_everything() {
+    let repo = create_repo();
+    write(
+        repo.path(),
+        "hasty.json",
+        r#"{ "pipeline": { "build": { "command": "build", "output": ["dist/**"] } } }"#,
+    );
+
+    assert_eq!(
+        affected_since(repo.path(), "HEAD"),
+        names(&["root", "a", "b", "c"])
+    );
+}
+
+#[test]
+fn unknown_ref_is_an_error() {
+    let repo = create_repo();
+    let raw = fs::read_to_string(repo.path().join("hasty.json")).unwrap();
+    let config: Config = serde_json::from_str(&raw).unwrap();
+
+    let mut engine = Engine::new(
+        config,
+        repo.path(),
+        vec![String::from("build")],
+        EngineOptions::default(),
+    )
+    .unwrap();
+    engine.build_package_graph().unwrap();
+
+    assert!(engine.filter_changed_since("does-not-exist").is_err());
+}
+
+#[test]
+fn changes_on_the_base_branch_are_ignored() {
+    let repo = create_repo();
+    git(repo.path(), &["checkout", "-q", "-b", "feature"]);
+    write(repo.path(), "packages/c/index.js", "module.exports = 1");
+    git(repo.path(), &["add", "-A"]);
+    git(repo.path(), &["commit", "-q", "-m", "change c"]);
+
+    // the base moves on after the feature branch forked from it
+    git(repo.path(), &["checkout", "-q", "-b", "base", "HEAD~1"]);
+    write(repo.path(), "packages/b/index.js", "module.exports = 2");
+    git(repo.path(), &["add", "-A"]);
+    git(repo.path(), &["commit", "-q", "-m", "change b"]);
+    git(repo.path(), &["checkout", "-q", "feature"]);
+
+    assert_eq!(affected_since(repo.path(), "base"), names(&["c"]));
+}
+
+#[test]
+fn non_ascii_paths_are_matched_to_their_package() {
+    let repo = create_repo();
+    write(repo.path(), "packages/b/héllo.js", "module.exports = 1");
+    write(repo.path(), "packages/c/wörld.js", "module.exports = 1");
+    git(repo.path(), &["add", "packages/c"]);
+    git(repo.path(), &["commit", "-q", "-m", "add c"]);
+
+    assert_eq!(affected_since(repo.path(), "HEAD"), names(&["a", "b"]));
+    assert_eq!(
+        affected_since(repo.path(), "HEAD~1"),
+        names(&["a", "b", "c"])
+    );
+}